@@ -1,9 +1,10 @@
+use ash::vk;
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::{Window, WindowId};
 
-use crate::graphics::GraphicsState;
+use crate::graphics::{GraphicsError, GraphicsResult, GraphicsState};
 use crate::APP_NAME;
 
 #[derive(Debug, Default)]
@@ -12,24 +13,79 @@ pub struct App {
     window: Option<Window>,
 }
 
+const HEADLESS_EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 800,
+    height: 600,
+};
+
 impl App {
     pub fn new() -> Self {
         Self::default()
     }
+
+    pub fn run_headless(&mut self, frame_count: u32) -> GraphicsResult<()> {
+        let graphics_state = self
+            .graphics_state
+            .insert(GraphicsState::new_headless(HEADLESS_EXTENT)?);
+
+        for _ in 0..frame_count {
+            graphics_state.render()?;
+        }
+
+        Ok(())
+    }
+
+    /// Skips the failed frame. A lost device is recreated together with every graphics object.
+    fn recover(&mut self, event_loop: &ActiveEventLoop, error: GraphicsError) {
+        log::error!("Rendering failed: {error}");
+
+        if !error.is_device_lost() {
+            return;
+        }
+
+        let Some(window) = self.window.as_ref() else {
+            return;
+        };
+
+        // The old device has to be destroyed before a new one is created
+        self.graphics_state = None;
+
+        match GraphicsState::new(window) {
+            Ok(graphics_state) => {
+                log::info!("Recreated graphics after device loss");
+                self.graphics_state = Some(graphics_state);
+            }
+            Err(e) => {
+                log::error!("Can't recreate graphics after device loss: {e}");
+                event_loop.exit();
+            }
+        }
+    }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window_attributes = Window::default_attributes().with_title(APP_NAME);
 
-        let window = event_loop
-            .create_window(window_attributes)
-            .expect("Error while create window");
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => window,
+            Err(e) => {
+                log::error!("Can't create window: {e}");
+                event_loop.exit();
+                return;
+            }
+        };
 
-        let graphics_state = GraphicsState::new(&window);
+        match GraphicsState::new(&window) {
+            Ok(graphics_state) => self.graphics_state = Some(graphics_state),
+            Err(e) => {
+                log::error!("Can't initialize graphics: {e}");
+                event_loop.exit();
+                return;
+            }
+        }
 
         self.window = Some(window);
-        self.graphics_state = Some(graphics_state);
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
@@ -42,18 +98,21 @@ impl ApplicationHandler for App {
             Some(state) => state,
         };
 
-        match event {
+        let result = match event {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
+                Ok(())
             }
-            WindowEvent::Resized(size) => {
-                graphics_state.resize(size);
-            }
+            WindowEvent::Resized(size) => graphics_state.resize(size),
             WindowEvent::RedrawRequested => {
-                graphics_state.render();
                 window.request_redraw();
+                graphics_state.render()
             }
-            _ => {}
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            self.recover(event_loop, e);
         }
     }
 }
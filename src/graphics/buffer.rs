@@ -0,0 +1,402 @@
+use super::debug_utils::DeviceDebugUtilsFns;
+use super::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, Queue, VulkanDevice};
+use super::memory::{Allocation, AllocationDescription, DeviceMemoryFns};
+use super::sync::SubmitInfo;
+use crate::{gfx_debug_exec, gfx_debug_log};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryLocation {
+    /// Device local memory, filled through [`Buffer::upload`]
+    #[default]
+    DeviceLocal,
+    /// Host visible memory written by the CPU and read by the GPU
+    HostVisible,
+    /// Host visible memory written by the GPU and read back by the CPU
+    Readback,
+}
+
+impl MemoryLocation {
+    fn required_properties(self) -> vk::MemoryPropertyFlags {
+        match self {
+            MemoryLocation::DeviceLocal => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryLocation::HostVisible | MemoryLocation::Readback => {
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            }
+        }
+    }
+
+    fn preferred_properties(self) -> vk::MemoryPropertyFlags {
+        match self {
+            MemoryLocation::Readback => vk::MemoryPropertyFlags::HOST_CACHED,
+            _ => vk::MemoryPropertyFlags::empty(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BufferDescription {
+    pub size: u64,
+    pub usage: vk::BufferUsageFlags,
+    pub location: MemoryLocation,
+}
+
+impl BufferDescription {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertex<T>(count: usize) -> Self {
+        Self::new()
+            .size(std::mem::size_of::<T>() as u64 * count as u64)
+            .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    pub fn index<T: IndexType>(count: usize) -> Self {
+        Self::new()
+            .size(std::mem::size_of::<T>() as u64 * count as u64)
+            .usage(vk::BufferUsageFlags::INDEX_BUFFER)
+    }
+
+    pub fn uniform<T>() -> Self {
+        Self::new()
+            .size(std::mem::size_of::<T>() as u64)
+            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+            .location(MemoryLocation::HostVisible)
+    }
+
+    pub fn storage(size: u64) -> Self {
+        Self::new()
+            .size(size)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+    }
+
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn usage(mut self, usage: vk::BufferUsageFlags) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn location(mut self, location: MemoryLocation) -> Self {
+        self.location = location;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct Buffer {
+    handle: vk::Buffer,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    location: MemoryLocation,
+    allocation: Allocation,
+    device: Arc<Device>,
+}
+
+impl Buffer {
+    /// Panics on empty buffers, Vulkan doesn't allow them
+    pub fn new(device: Arc<Device>, description: &BufferDescription) -> VkResult<Self> {
+        assert!(description.size > 0, "Buffers can't be empty");
+
+        let usage = match description.location {
+            MemoryLocation::DeviceLocal => description.usage | vk::BufferUsageFlags::TRANSFER_DST,
+            _ => description.usage,
+        };
+
+        let create_info = vk::BufferCreateInfo::default()
+            .size(description.size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let handle = device.create(&create_info)?;
+
+        let requirements = unsafe { device.handle().get_buffer_memory_requirements(handle) };
+        let allocation_description = AllocationDescription::new(requirements)
+            .required_properties(description.location.required_properties())
+            .preferred_properties(description.location.preferred_properties());
+
+        let allocation =
+            device
+                .allocate_memory(&allocation_description)
+                .and_then(|allocation| unsafe {
+                    device
+                        .handle()
+                        .bind_buffer_memory(handle, allocation.memory(), allocation.offset())
+                        .map(|_| allocation)
+                });
+
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy(handle);
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            handle,
+            size: description.size,
+            usage,
+            location: description.location,
+            allocation,
+            device,
+        })
+    }
+
+    /// Creates a host visible buffer filled with `data`
+    pub fn from_slice<T: Copy>(
+        device: Arc<Device>,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> VkResult<Self> {
+        let description = BufferDescription::new()
+            .size(std::mem::size_of_val(data) as u64)
+            .usage(usage)
+            .location(MemoryLocation::HostVisible);
+
+        let buffer = Self::new(device, &description)?;
+        buffer.write(0, data)?;
+
+        Ok(buffer)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+
+    pub fn location(&self) -> MemoryLocation {
+        self.location
+    }
+
+    /// Copies `data` into the mapped memory at byte `offset`.
+    /// Fails with `ERROR_MEMORY_MAP_FAILED` for device local buffers.
+    pub fn write<T: Copy>(&self, offset: u64, data: &[T]) -> VkResult<()> {
+        let ptr = self
+            .allocation
+            .mapped_ptr()
+            .ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+
+        let size = std::mem::size_of_val(data) as u64;
+        assert!(
+            offset + size <= self.size,
+            "Write of {size} bytes at {offset} is out of buffer bounds ({})",
+            self.size
+        );
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                ptr.as_ptr().add(offset as usize),
+                size as usize,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of the mapped memory.
+    /// The caller must make sure the GPU has finished writing to the buffer.
+    pub fn read_bytes(&self) -> VkResult<Vec<u8>> {
+        let ptr = self
+            .allocation
+            .mapped_ptr()
+            .ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+
+        Ok(unsafe { std::slice::from_raw_parts(ptr.as_ptr(), self.size as usize) }.to_vec())
+    }
+
+    /// Copies `data` to the start of the buffer. Host visible buffers are written directly,
+    /// device local ones go through a staging buffer and a blocking transfer on `queue`.
+    pub fn upload<T: Copy>(&self, queue: &Queue, data: &[T]) -> VkResult<()> {
+        let size = std::mem::size_of_val(data) as u64;
+        assert!(
+            size <= self.size,
+            "Upload of {size} bytes is out of buffer bounds ({})",
+            self.size
+        );
+
+        if size == 0 {
+            return Ok(());
+        }
+
+        if self.location != MemoryLocation::DeviceLocal {
+            return self.write(0, data);
+        }
+
+        let staging = Self::from_slice(
+            self.device.clone(),
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            data,
+        )?;
+
+        let command_pool_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue.family_index())
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+
+        let device = self.device.handle();
+        let command_pool = unsafe { device.create_command_pool(&command_pool_info, None)? };
+
+        let result = (|| unsafe {
+            let command_buffer_info = vk::CommandBufferAllocateInfo::default()
+                .command_pool(command_pool)
+                .command_buffer_count(1)
+                .level(vk::CommandBufferLevel::PRIMARY);
+
+            let command_buffer = device.allocate_command_buffers(&command_buffer_info)?[0];
+
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+
+            let region = vk::BufferCopy::default().size(size);
+            device.cmd_copy_buffer(
+                command_buffer,
+                staging.handle,
+                self.handle,
+                std::slice::from_ref(&region),
+            );
+
+            device.end_command_buffer(command_buffer)?;
+
+            let guard = queue.lock();
+            let point = queue.timeline().next_point();
+            SubmitInfo::new()
+                .command_buffers(vec![command_buffer])
+                .signal(point.handle(), point.value())
+                .submit(&guard, vk::Fence::null())?;
+            drop(guard);
+
+            point.wait(u64::MAX)
+        })();
+
+        unsafe { device.destroy_command_pool(command_pool, None) };
+
+        result
+    }
+
+    /// Typed view over `range` elements of `T`
+    pub fn slice<T>(&self, range: Range<usize>) -> BufferSlice<'_, T> {
+        let element_size = std::mem::size_of::<T>() as u64;
+        let offset = range.start as u64 * element_size;
+
+        assert!(
+            range.end as u64 * element_size <= self.size,
+            "Slice {range:?} is out of buffer bounds"
+        );
+
+        BufferSlice {
+            buffer: self,
+            offset,
+            len: range.len(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Typed view over the whole buffer
+    pub fn as_slice<T>(&self) -> BufferSlice<'_, T> {
+        let len = self.size / std::mem::size_of::<T>() as u64;
+
+        self.slice(0..len as usize)
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(Buffer::drop()));
+        self.device.destroy(self.handle);
+    }
+}
+
+#[derive(Debug)]
+pub struct BufferSlice<'a, T> {
+    buffer: &'a Buffer,
+    offset: u64,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for BufferSlice<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for BufferSlice<'_, T> {}
+
+impl<T> BufferSlice<'_, T> {
+    pub fn buffer(&self) -> &Buffer {
+        self.buffer
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn size(&self) -> u64 {
+        self.len as u64 * std::mem::size_of::<T>() as u64
+    }
+}
+
+impl<T: IndexType> BufferSlice<'_, T> {
+    pub fn index_type(&self) -> vk::IndexType {
+        T::INDEX_TYPE
+    }
+}
+
+pub trait IndexType: Copy {
+    const INDEX_TYPE: vk::IndexType;
+}
+
+impl IndexType for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl IndexType for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+impl DeviceCreateExtend<vk::BufferCreateInfo<'_>, vk::Buffer> for Device {
+    fn create(&self, create_info: &vk::BufferCreateInfo<'_>) -> VkResult<vk::Buffer> {
+        unsafe { self.handle().create_buffer(create_info, None) }
+    }
+}
+
+impl DeviceDestroyExtend<vk::Buffer> for Device {
+    fn destroy(&self, vk_struct: vk::Buffer) {
+        self.defer_destroy(move |device| unsafe {
+            device.handle().destroy_buffer(vk_struct, None);
+        });
+    }
+}
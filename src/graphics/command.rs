@@ -0,0 +1,776 @@
+use crate::graphics::buffer::{Buffer, BufferSlice, IndexType};
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use crate::graphics::pipeline::Pipeline;
+use crate::{gfx_debug_exec, gfx_debug_log};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderState {
+    Recording,
+    /// Between [`CommandEncoder::begin_rendering`] and [`CommandEncoder::end_rendering`]
+    Rendering,
+    Ended,
+}
+
+impl EncoderState {
+    fn require(self, expected: EncoderState) -> Result<(), EncoderError> {
+        if self == expected {
+            Ok(())
+        } else {
+            Err(EncoderError::InvalidState {
+                expected,
+                actual: self,
+            })
+        }
+    }
+
+    /// State commands valid both inside and outside of rendering can be recorded in
+    fn require_open(self) -> Result<(), EncoderError> {
+        match self {
+            EncoderState::Recording | EncoderState::Rendering => Ok(()),
+            EncoderState::Ended => Err(EncoderError::InvalidState {
+                expected: EncoderState::Recording,
+                actual: self,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderError {
+    InvalidState {
+        expected: EncoderState,
+        actual: EncoderState,
+    },
+    /// Draw or push constants without a bound pipeline
+    NoPipeline,
+    /// Secondary command buffers continue the rendering of the primary one and can't end it
+    InheritedRendering,
+    /// Vertex buffers and their offsets have different lengths
+    VertexBufferOffsets {
+        buffers: usize,
+        offsets: usize,
+    },
+    Vk(vk::Result),
+}
+
+impl Display for EncoderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncoderError::InvalidState { expected, actual } => {
+                write!(f, "command encoder is {actual:?}, expected {expected:?}")
+            }
+            EncoderError::NoPipeline => write!(f, "no pipeline bound"),
+            EncoderError::InheritedRendering => {
+                write!(f, "rendering is owned by the primary command buffer")
+            }
+            EncoderError::VertexBufferOffsets { buffers, offsets } => {
+                write!(f, "{buffers} vertex buffers bound with {offsets} offsets")
+            }
+            EncoderError::Vk(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for EncoderError {}
+
+impl From<vk::Result> for EncoderError {
+    fn from(value: vk::Result) -> Self {
+        EncoderError::Vk(value)
+    }
+}
+
+/// Records into a command buffer, rejecting commands that are invalid in its current state,
+/// e.g. draws outside of rendering or copies inside of it. Encoders dropped before
+/// [`CommandEncoder::end`] end the command buffer themselves.
+#[derive(Debug)]
+pub struct CommandEncoder {
+    command_buffer: vk::CommandBuffer,
+    state: EncoderState,
+    inherited_rendering: bool,
+    pipeline_layout: Option<vk::PipelineLayout>,
+    device: Arc<Device>,
+}
+
+/// Attachments of the rendering a secondary command buffer is executed in
+#[derive(Debug, Clone, Default)]
+pub struct SecondaryInheritance {
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: vk::Format,
+}
+
+impl SecondaryInheritance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn color_formats(mut self, color_formats: Vec<vk::Format>) -> Self {
+        self.color_formats = color_formats;
+        self
+    }
+
+    pub fn depth_format(mut self, depth_format: vk::Format) -> Self {
+        self.depth_format = depth_format;
+        self
+    }
+}
+
+impl CommandEncoder {
+    /// Begins recording `command_buffer`, which has to be reset
+    pub fn begin(
+        device: Arc<Device>,
+        command_buffer: vk::CommandBuffer,
+        flags: vk::CommandBufferUsageFlags,
+    ) -> Result<Self, EncoderError> {
+        let begin_info = vk::CommandBufferBeginInfo::default().flags(flags);
+
+        unsafe {
+            device
+                .handle()
+                .begin_command_buffer(command_buffer, &begin_info)?
+        };
+
+        Ok(Self {
+            command_buffer,
+            state: EncoderState::Recording,
+            inherited_rendering: false,
+            pipeline_layout: None,
+            device,
+        })
+    }
+
+    /// Begins recording a secondary `command_buffer` that is executed inside dynamic
+    /// rendering with the attachments of `inheritance`. Recording starts in
+    /// [`EncoderState::Rendering`] and ends in it.
+    pub fn begin_secondary(
+        device: Arc<Device>,
+        command_buffer: vk::CommandBuffer,
+        inheritance: &SecondaryInheritance,
+    ) -> Result<Self, EncoderError> {
+        let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::default()
+            .color_attachment_formats(&inheritance.color_formats)
+            .depth_attachment_format(inheritance.depth_format)
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let inheritance_info =
+            vk::CommandBufferInheritanceInfo::default().push_next(&mut rendering_info);
+
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info);
+
+        unsafe {
+            device
+                .handle()
+                .begin_command_buffer(command_buffer, &begin_info)?
+        };
+
+        Ok(Self {
+            command_buffer,
+            state: EncoderState::Rendering,
+            inherited_rendering: true,
+            pipeline_layout: None,
+            device,
+        })
+    }
+
+    pub fn handle(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    pub fn state(&self) -> EncoderState {
+        self.state
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// Finishes recording, the returned command buffer is ready to be submitted
+    pub fn end(&mut self) -> Result<vk::CommandBuffer, EncoderError> {
+        if self.inherited_rendering {
+            self.state.require(EncoderState::Rendering)?;
+        } else {
+            self.state.require(EncoderState::Recording)?;
+        }
+
+        unsafe {
+            self.device
+                .handle()
+                .end_command_buffer(self.command_buffer)?
+        };
+        self.state = EncoderState::Ended;
+
+        Ok(self.command_buffer)
+    }
+
+    pub fn pipeline_barrier(
+        &mut self,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        buffer_barriers: &[vk::BufferMemoryBarrier<'_>],
+        image_barriers: &[vk::ImageMemoryBarrier<'_>],
+    ) -> Result<(), EncoderError> {
+        self.state.require(EncoderState::Recording)?;
+
+        unsafe {
+            self.device.handle().cmd_pipeline_barrier(
+                self.command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                buffer_barriers,
+                image_barriers,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn begin_rendering(
+        &mut self,
+        rendering_info: &vk::RenderingInfo<'_>,
+    ) -> Result<(), EncoderError> {
+        self.state.require(EncoderState::Recording)?;
+
+        unsafe {
+            self.device
+                .handle()
+                .cmd_begin_rendering(self.command_buffer, rendering_info)
+        };
+        self.state = EncoderState::Rendering;
+
+        Ok(())
+    }
+
+    pub fn end_rendering(&mut self) -> Result<(), EncoderError> {
+        if self.inherited_rendering {
+            return Err(EncoderError::InheritedRendering);
+        }
+        self.state.require(EncoderState::Rendering)?;
+
+        unsafe { self.device.handle().cmd_end_rendering(self.command_buffer) };
+        self.state = EncoderState::Recording;
+
+        Ok(())
+    }
+
+    /// Opens a labeled region shown by capture tools, see [`CommandEncoder::label`]
+    pub fn begin_label(&mut self, name: &str, color: [f32; 4]) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+        gfx_debug_exec!(self.device.begin_label(self.command_buffer, name, color));
+
+        Ok(())
+    }
+
+    pub fn end_label(&mut self) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+        gfx_debug_exec!(self.device.end_label(self.command_buffer));
+
+        Ok(())
+    }
+
+    /// Records the commands of `record` inside a labeled region
+    pub fn label<T>(
+        &mut self,
+        name: &str,
+        color: [f32; 4],
+        record: impl FnOnce(&mut Self) -> Result<T, EncoderError>,
+    ) -> Result<T, EncoderError> {
+        self.begin_label(name, color)?;
+        let result = record(self)?;
+        self.end_label()?;
+
+        Ok(result)
+    }
+
+    pub fn bind_pipeline(&mut self, pipeline: &Pipeline) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+
+        unsafe {
+            self.device.handle().cmd_bind_pipeline(
+                self.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.handle(),
+            );
+        }
+        self.pipeline_layout = Some(pipeline.layout().handle());
+
+        Ok(())
+    }
+
+    pub fn set_viewport(&mut self, viewport: vk::Viewport) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+
+        unsafe {
+            self.device.handle().cmd_set_viewport(
+                self.command_buffer,
+                0,
+                std::slice::from_ref(&viewport),
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn set_scissor(&mut self, scissor: vk::Rect2D) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+
+        unsafe {
+            self.device.handle().cmd_set_scissor(
+                self.command_buffer,
+                0,
+                std::slice::from_ref(&scissor),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Pushes `data` into the layout of the bound pipeline
+    pub fn push_constants(
+        &mut self,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+        let layout = self.pipeline_layout.ok_or(EncoderError::NoPipeline)?;
+
+        unsafe {
+            self.device.handle().cmd_push_constants(
+                self.command_buffer,
+                layout,
+                stage_flags,
+                offset,
+                data,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Binds `buffers` to consecutive bindings, each read from its byte offset in `offsets`
+    pub fn bind_vertex_buffers(
+        &mut self,
+        first_binding: u32,
+        buffers: &[&Buffer],
+        offsets: &[vk::DeviceSize],
+    ) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+        if buffers.len() != offsets.len() {
+            return Err(EncoderError::VertexBufferOffsets {
+                buffers: buffers.len(),
+                offsets: offsets.len(),
+            });
+        }
+
+        let handles: Vec<_> = buffers.iter().map(|buffer| buffer.handle()).collect();
+
+        unsafe {
+            self.device.handle().cmd_bind_vertex_buffers(
+                self.command_buffer,
+                first_binding,
+                &handles,
+                offsets,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Binds the indices of `indices`, the index type follows from `T`
+    pub fn bind_index_buffer<T: IndexType>(
+        &mut self,
+        indices: BufferSlice<'_, T>,
+    ) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+
+        unsafe {
+            self.device.handle().cmd_bind_index_buffer(
+                self.command_buffer,
+                indices.handle(),
+                indices.offset(),
+                T::INDEX_TYPE,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn draw(
+        &mut self,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) -> Result<(), EncoderError> {
+        self.require_draw()?;
+
+        unsafe {
+            self.device.handle().cmd_draw(
+                self.command_buffer,
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn draw_indexed(
+        &mut self,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) -> Result<(), EncoderError> {
+        self.require_draw()?;
+
+        unsafe {
+            self.device.handle().cmd_draw_indexed(
+                self.command_buffer,
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn copy_buffer(
+        &mut self,
+        src: &Buffer,
+        dst: &Buffer,
+        regions: &[vk::BufferCopy],
+    ) -> Result<(), EncoderError> {
+        self.state.require(EncoderState::Recording)?;
+
+        unsafe {
+            self.device.handle().cmd_copy_buffer(
+                self.command_buffer,
+                src.handle(),
+                dst.handle(),
+                regions,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn copy_buffer_to_image(
+        &mut self,
+        src: &Buffer,
+        dst: vk::Image,
+        dst_layout: vk::ImageLayout,
+        regions: &[vk::BufferImageCopy],
+    ) -> Result<(), EncoderError> {
+        self.state.require(EncoderState::Recording)?;
+
+        unsafe {
+            self.device.handle().cmd_copy_buffer_to_image(
+                self.command_buffer,
+                src.handle(),
+                dst,
+                dst_layout,
+                regions,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn copy_image_to_buffer(
+        &mut self,
+        src: vk::Image,
+        src_layout: vk::ImageLayout,
+        dst: &Buffer,
+        regions: &[vk::BufferImageCopy],
+    ) -> Result<(), EncoderError> {
+        self.state.require(EncoderState::Recording)?;
+
+        unsafe {
+            self.device.handle().cmd_copy_image_to_buffer(
+                self.command_buffer,
+                src,
+                src_layout,
+                dst.handle(),
+                regions,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Executes the command buffers of ended secondary encoders. Inside rendering, the
+    /// rendering has to be begun with `CONTENTS_SECONDARY_COMMAND_BUFFERS`.
+    pub fn execute_commands(&mut self, secondaries: &[CommandEncoder]) -> Result<(), EncoderError> {
+        self.state.require_open()?;
+        if self.inherited_rendering {
+            return Err(EncoderError::InheritedRendering);
+        }
+
+        let command_buffers = secondaries
+            .iter()
+            .map(|secondary| {
+                secondary.state.require(EncoderState::Ended)?;
+                Ok(secondary.command_buffer)
+            })
+            .collect::<Result<Vec<_>, EncoderError>>()?;
+
+        unsafe {
+            self.device
+                .handle()
+                .cmd_execute_commands(self.command_buffer, &command_buffers);
+        }
+
+        Ok(())
+    }
+
+    fn require_draw(&self) -> Result<(), EncoderError> {
+        self.state.require(EncoderState::Rendering)?;
+        self.pipeline_layout
+            .map(|_| ())
+            .ok_or(EncoderError::NoPipeline)
+    }
+}
+
+impl Drop for CommandEncoder {
+    fn drop(&mut self) {
+        if self.state == EncoderState::Ended {
+            return;
+        }
+
+        log::warn!(
+            target: "rust_engine::graphics",
+            "Command encoder dropped while {:?}, ending its command buffer",
+            self.state
+        );
+
+        unsafe {
+            if self.state == EncoderState::Rendering && !self.inherited_rendering {
+                self.device.handle().cmd_end_rendering(self.command_buffer);
+            }
+
+            if let Err(e) = self.device.handle().end_command_buffer(self.command_buffer) {
+                log::error!(target: "rust_engine::graphics", "Can't end command buffer: {e}");
+            }
+        }
+    }
+}
+
+/// Pools are externally synchronized, so everything that records from them takes `&mut self`
+#[derive(Debug)]
+pub struct CommandPool {
+    handle: vk::CommandPool,
+    queue_family_index: u32,
+    /// Secondary command buffers handed out since the last reset come first
+    secondary: Vec<vk::CommandBuffer>,
+    secondary_used: usize,
+    device: Arc<Device>,
+}
+
+impl CommandPool {
+    pub fn new(
+        device: Arc<Device>,
+        queue_family_index: u32,
+        flags: vk::CommandPoolCreateFlags,
+    ) -> VkResult<Self> {
+        let create_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .flags(flags);
+
+        let handle = device.create(&create_info)?;
+
+        Ok(Self {
+            handle,
+            queue_family_index,
+            secondary: vec![],
+            secondary_used: 0,
+            device,
+        })
+    }
+
+    pub fn handle(&self) -> vk::CommandPool {
+        self.handle
+    }
+
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+
+    pub fn queue_family_index(&self) -> u32 {
+        self.queue_family_index
+    }
+
+    /// Command buffers are freed together with the pool
+    pub fn allocate(
+        &mut self,
+        level: vk::CommandBufferLevel,
+        count: u32,
+    ) -> VkResult<Vec<vk::CommandBuffer>> {
+        let allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(self.handle)
+            .command_buffer_count(count)
+            .level(level);
+
+        unsafe {
+            self.device
+                .handle()
+                .allocate_command_buffers(&allocate_info)
+        }
+    }
+
+    /// Secondary command buffer that is handed out again after the next reset
+    pub fn secondary(&mut self) -> VkResult<vk::CommandBuffer> {
+        if self.secondary_used == self.secondary.len() {
+            let command_buffer = self.allocate(vk::CommandBufferLevel::SECONDARY, 1)?[0];
+            self.secondary.push(command_buffer);
+        }
+
+        self.secondary_used += 1;
+
+        Ok(self.secondary[self.secondary_used - 1])
+    }
+
+    /// Resets every command buffer of the pool, none of them may be pending
+    pub fn reset(&mut self) -> VkResult<()> {
+        self.secondary_used = 0;
+
+        unsafe {
+            self.device
+                .handle()
+                .reset_command_pool(self.handle, vk::CommandPoolResetFlags::empty())
+        }
+    }
+}
+
+/// One [`CommandPool`] per recording thread, so worker threads can record secondary
+/// command buffers in parallel without sharing a pool
+#[derive(Debug)]
+pub struct ThreadCommandPools {
+    queue_family_index: u32,
+    pools: Mutex<HashMap<ThreadId, ThreadPool>>,
+    device: Arc<Device>,
+}
+
+#[derive(Debug)]
+struct ThreadPool {
+    pool: Arc<Mutex<CommandPool>>,
+    /// Recorded from since the last reset
+    used: bool,
+}
+
+impl ThreadCommandPools {
+    pub fn new(device: Arc<Device>, queue_family_index: u32) -> Self {
+        Self {
+            queue_family_index,
+            pools: Mutex::new(HashMap::new()),
+            device,
+        }
+    }
+
+    /// Pool of the calling thread, created on first use. Only the calling thread locks it
+    /// while recording, so the lock is uncontended.
+    pub fn current(&self) -> VkResult<Arc<Mutex<CommandPool>>> {
+        let mut pools = self.pools.lock().unwrap();
+
+        if let Some(entry) = pools.get_mut(&std::thread::current().id()) {
+            entry.used = true;
+            return Ok(entry.pool.clone());
+        }
+
+        let pool = Arc::new(Mutex::new(CommandPool::new(
+            self.device.clone(),
+            self.queue_family_index,
+            vk::CommandPoolCreateFlags::TRANSIENT,
+        )?));
+        pools.insert(
+            std::thread::current().id(),
+            ThreadPool {
+                pool: pool.clone(),
+                used: true,
+            },
+        );
+
+        Ok(pool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Resets the pools of all threads, none of their command buffers may be pending.
+    /// Pools of threads that didn't record since the last reset are destroyed, so
+    /// short-lived threads don't leave pools behind.
+    pub fn reset(&self) -> VkResult<()> {
+        let mut pools = self.pools.lock().unwrap();
+        pools.retain(|_, entry| entry.used);
+
+        pools.values_mut().try_for_each(|entry| {
+            entry.used = false;
+            entry.pool.lock().unwrap().reset()
+        })
+    }
+}
+
+impl Drop for CommandPool {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(CommandPool::drop()));
+        self.device.destroy(self.handle);
+    }
+}
+
+impl DeviceCreateExtend<vk::CommandPoolCreateInfo<'_>, vk::CommandPool> for Device {
+    fn create(&self, create_info: &vk::CommandPoolCreateInfo<'_>) -> VkResult<vk::CommandPool> {
+        unsafe { self.handle().create_command_pool(create_info, None) }
+    }
+}
+
+impl DeviceDestroyExtend<vk::CommandPool> for Device {
+    fn destroy(&self, vk_struct: vk::CommandPool) {
+        unsafe { self.handle().destroy_command_pool(vk_struct, None) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_checks() {
+        assert!(EncoderState::Recording
+            .require(EncoderState::Recording)
+            .is_ok());
+        assert!(EncoderState::Rendering.require_open().is_ok());
+
+        assert_eq!(
+            EncoderState::Rendering.require(EncoderState::Recording),
+            Err(EncoderError::InvalidState {
+                expected: EncoderState::Recording,
+                actual: EncoderState::Rendering,
+            })
+        );
+        assert!(EncoderState::Ended.require_open().is_err());
+    }
+}
@@ -1,26 +1,32 @@
+use super::device::Device;
 use super::instance::Instance;
 use crate::gfx_debug_log;
 use ash::vk;
-use std::ffi::c_void;
-use std::fmt::{Debug, Formatter};
+use std::borrow::Cow;
+use std::ffi::{c_char, c_void, CStr};
+use std::fmt::{Debug, Display, Formatter};
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 pub struct DebugUtils {
     debug_instance: ash::ext::debug_utils::Instance,
     debug_utils_messenger: vk::DebugUtilsMessengerEXT,
-    instance: Rc<Instance>,
+    /// Boxed so the callback can keep a pointer to it
+    _sink: Option<Box<Arc<dyn DebugMessageSink>>>,
+    instance: Arc<Instance>,
 }
 
 impl DebugUtils {
-    pub(self) fn new(
+    fn new(
         debug_instance: ash::ext::debug_utils::Instance,
         debug_utils_messenger: vk::DebugUtilsMessengerEXT,
-        instance: Rc<Instance>,
+        sink: Option<Box<Arc<dyn DebugMessageSink>>>,
+        instance: Arc<Instance>,
     ) -> Self {
         Self {
             debug_instance,
             debug_utils_messenger,
+            _sink: sink,
             instance,
         }
     }
@@ -31,6 +37,7 @@ impl Debug for DebugUtils {
         f.debug_struct("DebugUtils")
             .field("debug_instance", &std::ptr::addr_of!(self.debug_instance))
             .field("debug_utils_messenger", &self.debug_utils_messenger)
+            .field("sink", &self._sink.is_some())
             .field("instance", &self.instance)
             .finish()
     }
@@ -46,38 +53,153 @@ impl Drop for DebugUtils {
     }
 }
 
-#[non_exhaustive]
-pub struct DebugUtilsMessengerCallbackData<'a> {
-    pub message_id_name: Option<&'a str>,
-    pub message_id_number: i32,
-    pub message: &'a str,
+/// Object a debug message refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugObject {
+    pub object_type: vk::ObjectType,
+    pub handle: u64,
+    /// Name given with [`DeviceDebugUtilsFns::set_object_name`]
+    pub name: Option<String>,
 }
 
-impl<'a> From<vk::DebugUtilsMessengerCallbackDataEXT<'_>> for DebugUtilsMessengerCallbackData<'a> {
-    fn from(value: vk::DebugUtilsMessengerCallbackDataEXT<'_>) -> Self {
-        let vk::DebugUtilsMessengerCallbackDataEXT {
-            p_message_id_name,
-            message_id_number,
-            p_message,
-            ..
-        } = value;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Validation ID, e.g. `VUID-vkCmdDraw-None-08606`
+    pub id_name: Option<String>,
+    pub id_number: i32,
+    pub message: String,
+    pub objects: Vec<DebugObject>,
+}
 
-        unsafe {
-            Self {
-                message_id_name: p_message_id_name
-                    .as_ref()
-                    .map(|t| std::ffi::CStr::from_ptr(t).to_str().unwrap()),
-                message_id_number,
-                message: std::ffi::CStr::from_ptr(p_message).to_str().unwrap(),
+impl DebugMessage {
+    /// # Safety
+    /// `data` has to be the callback data passed to the messenger callback
+    unsafe fn from_raw(
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+        data: &vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    ) -> Self {
+        let objects = if data.p_objects.is_null() {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(data.p_objects, data.object_count as usize)
+        };
+
+        Self {
+            severity,
+            message_type,
+            id_name: c_string(data.p_message_id_name).map(Cow::into_owned),
+            id_number: data.message_id_number,
+            message: c_string(data.p_message)
+                .map(Cow::into_owned)
+                .unwrap_or_default(),
+            objects: objects
+                .iter()
+                .map(|object| DebugObject {
+                    object_type: object.object_type,
+                    handle: object.object_handle,
+                    name: c_string(object.p_object_name).map(Cow::into_owned),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity
+            .contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+    }
+}
+
+impl Display for DebugMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}::{:?}]", self.severity, self.message_type)?;
+        if let Some(id_name) = &self.id_name {
+            write!(f, " {id_name} ({:#x})", self.id_number)?;
+        }
+        write!(f, ": {}", self.message)?;
+
+        for object in &self.objects {
+            write!(f, "\n  {:?} {:#x}", object.object_type, object.handle)?;
+            if let Some(name) = &object.name {
+                write!(f, " \"{name}\"")?;
             }
         }
+
+        Ok(())
+    }
+}
+
+/// # Safety
+/// `ptr` has to be null or point to a nul terminated string
+unsafe fn c_string<'a>(ptr: *const c_char) -> Option<Cow<'a, str>> {
+    ptr.as_ref()
+        .map(|ptr| CStr::from_ptr(ptr).to_string_lossy())
+}
+
+/// Receives the messages of a [`DebugUtils`] messenger. Messages can come from any
+/// thread that calls into Vulkan.
+pub trait DebugMessageSink: Send + Sync {
+    fn message(&self, message: &DebugMessage);
+}
+
+impl<F: Fn(&DebugMessage) + Send + Sync> DebugMessageSink for F {
+    fn message(&self, message: &DebugMessage) {
+        self(message)
+    }
+}
+
+/// Sink that keeps every message, e.g. to check them in tests
+#[derive(Debug, Default)]
+pub struct DebugMessageCollector {
+    messages: Mutex<Vec<DebugMessage>>,
+}
+
+impl DebugMessageCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> Vec<DebugMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    pub fn errors(&self) -> Vec<DebugMessage> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.is_error())
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.messages.lock().unwrap().clear();
+    }
+}
+
+impl DebugMessageSink for DebugMessageCollector {
+    fn message(&self, message: &DebugMessage) {
+        self.messages.lock().unwrap().push(message.clone());
     }
 }
 
-#[derive(Debug)]
 pub struct DebugUtilsBuilder {
     pub message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub sink: Option<Arc<dyn DebugMessageSink>>,
+}
+
+impl Debug for DebugUtilsBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugUtilsBuilder")
+            .field("message_severity", &self.message_severity)
+            .field("message_type", &self.message_type)
+            .field("sink", &self.sink.is_some())
+            .finish()
+    }
 }
 
 impl Default for DebugUtilsBuilder {
@@ -90,6 +212,7 @@ impl Default for DebugUtilsBuilder {
             message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
                 | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
                 | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            sink: None,
         }
     }
 }
@@ -112,11 +235,23 @@ impl DebugUtilsBuilder {
         self
     }
 
-    pub fn build(self, instance: Rc<Instance>) -> ash::prelude::VkResult<DebugUtils> {
+    /// Also sends the messages to `sink`, they are logged either way
+    pub fn sink(mut self, sink: Arc<dyn DebugMessageSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn build(self, instance: Arc<Instance>) -> ash::prelude::VkResult<DebugUtils> {
+        let sink = self.sink.map(Box::new);
+        let user_data = sink.as_deref().map_or(std::ptr::null_mut(), |sink| {
+            sink as *const Arc<dyn DebugMessageSink> as *mut c_void
+        });
+
         let debug_utils_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
             .message_severity(self.message_severity)
             .message_type(self.message_type)
-            .pfn_user_callback(Some(raw_debug_callback));
+            .pfn_user_callback(Some(raw_debug_callback))
+            .user_data(user_data);
 
         let debug_instance =
             ash::ext::debug_utils::Instance::new(&instance.entry(), &instance.handle());
@@ -126,69 +261,211 @@ impl DebugUtilsBuilder {
         Ok(DebugUtils::new(
             debug_instance,
             debug_utils_messenger,
+            sink,
             instance,
         ))
     }
 }
 
-pub(self) unsafe extern "system" fn raw_debug_callback(
+/// Object names and command buffer labels shown by validation messages and capture tools.
+/// Callers go through `gfx_debug_exec!` so they are compiled out without `gfx_debug_msg`,
+/// the calls are ignored when the instance has no `VK_EXT_debug_utils`.
+pub trait DeviceDebugUtilsFns {
+    fn set_object_name<T: vk::Handle>(&self, handle: T, name: &str);
+    fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]);
+    fn end_label(&self, command_buffer: vk::CommandBuffer);
+}
+
+impl DeviceDebugUtilsFns for Device {
+    fn set_object_name<T: vk::Handle>(&self, handle: T, name: &str) {
+        if let Some(debug_utils_fns) = self.debug_utils_fns() {
+            let name = std::ffi::CString::new(name).unwrap_or_default();
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+                .object_handle(handle)
+                .object_name(&name);
+
+            if let Err(e) = unsafe { debug_utils_fns.set_debug_utils_object_name(&name_info) } {
+                log::warn!(target: "rust_engine::graphics", "Can't name {name:?}: {e}");
+            }
+        }
+    }
+
+    fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils_fns) = self.debug_utils_fns() {
+            let name = std::ffi::CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::default()
+                .label_name(&name)
+                .color(color);
+
+            unsafe { debug_utils_fns.cmd_begin_debug_utils_label(command_buffer, &label) };
+        }
+    }
+
+    fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils_fns) = self.debug_utils_fns() {
+            unsafe { debug_utils_fns.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+}
+
+unsafe extern "system" fn raw_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_types: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
-    _: *mut c_void,
+    p_user_data: *mut c_void,
 ) -> vk::Bool32 {
     let _ = catch_unwind(AssertUnwindSafe(move || {
-        let raw_callback_data = *p_callback_data;
-
-        let data = DebugUtilsMessengerCallbackData::from(raw_callback_data);
+        let message = DebugMessage::from_raw(message_severity, message_types, &*p_callback_data);
 
         match message_severity {
             vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
-                log::error!(target: "rust_engine", "[{:?}::{:?}]: {}", message_severity, message_types, data.message)
+                log::error!(target: "rust_engine", "{message}")
             }
             _ => {
-                gfx_debug_log!(
-                    "[{:?}::{:?}]: {}",
-                    message_severity,
-                    message_types,
-                    data.message
-                );
+                gfx_debug_log!("{message}");
             }
         }
+
+        if let Some(sink) = (p_user_data as *const Arc<dyn DebugMessageSink>).as_ref() {
+            sink.message(&message);
+        }
     }));
 
     vk::FALSE
 }
 
+/// Fails the test if the validation layer reported an error before it is dropped.
+/// Create it right after the instance, so the destruction of the objects under test
+/// is validated as well.
 #[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::graphics::instance::InstanceBuilder;
+pub(crate) struct ValidationCapture {
+    collector: Arc<DebugMessageCollector>,
+    _debug_utils: DebugUtils,
+}
 
-    fn create_instance() -> Rc<Instance> {
-        InstanceBuilder::new()
+#[cfg(test)]
+impl ValidationCapture {
+    /// Instance builder with the validation layer and `VK_EXT_debug_utils` enabled
+    pub fn instance_builder() -> super::instance::InstanceBuilder {
+        super::instance::InstanceBuilder::new()
             .extensions(vec![ash::ext::debug_utils::NAME
                 .to_str()
                 .unwrap()
                 .to_string()])
             .layers(vec!["VK_LAYER_KHRONOS_validation".to_owned()])
             .api_version(vk::API_VERSION_1_3)
-            .build()
-            .unwrap()
+    }
+
+    pub fn new(instance: Arc<Instance>) -> Self {
+        let collector = Arc::new(DebugMessageCollector::new());
+        let debug_utils = DebugUtilsBuilder::new()
+            .sink(collector.clone())
+            .build(instance)
+            .unwrap();
+
+        Self {
+            collector,
+            _debug_utils: debug_utils,
+        }
+    }
+
+    pub fn messages(&self) -> Vec<DebugMessage> {
+        self.collector.messages()
+    }
+}
+
+#[cfg(test)]
+impl Drop for ValidationCapture {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+
+        let errors = self.collector.errors();
+        if !errors.is_empty() {
+            let errors: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+            panic!("Validation errors:\n{}", errors.join("\n"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn create_instance() -> Arc<Instance> {
+        ValidationCapture::instance_builder().build().unwrap()
     }
 
     #[test]
     fn test_create_debug_utils() {
         let instance = create_instance();
+        let _validation = ValidationCapture::new(instance.clone());
 
         let debug_utils = DebugUtilsBuilder::new().build(instance.clone());
 
         assert!(debug_utils.is_ok());
     }
 
+    #[test]
+    fn test_sink_receives_messages() {
+        let collector = Arc::new(DebugMessageCollector::new());
+        let sink: Arc<dyn DebugMessageSink> = collector.clone();
+
+        let id_name = c"VUID-vkDestroyBuffer-buffer-00922";
+        let message = c"buffer is in use";
+        let object_name = c"triangle vertices";
+        let objects = [vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(vk::Buffer::from_raw(0x42))
+            .object_name(object_name)];
+        let data = vk::DebugUtilsMessengerCallbackDataEXT::default()
+            .message_id_name(id_name)
+            .message_id_number(7)
+            .message(message)
+            .objects(&objects);
+
+        for severity in [
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        ] {
+            unsafe {
+                raw_debug_callback(
+                    severity,
+                    vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                    &data,
+                    &sink as *const Arc<dyn DebugMessageSink> as *mut c_void,
+                )
+            };
+        }
+
+        assert_eq!(collector.messages().len(), 2);
+
+        let errors = collector.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].id_name.as_deref(),
+            Some("VUID-vkDestroyBuffer-buffer-00922")
+        );
+        assert_eq!(errors[0].id_number, 7);
+        assert_eq!(errors[0].message, "buffer is in use");
+        assert_eq!(
+            errors[0].objects,
+            vec![DebugObject {
+                object_type: vk::ObjectType::BUFFER,
+                handle: 0x42,
+                name: Some("triangle vertices".to_owned()),
+            }]
+        );
+
+        collector.clear();
+        assert!(collector.messages().is_empty());
+    }
+
     #[test]
     fn test_create_debug_utils_without_callback() {
         let instance = create_instance();
+        let _validation = ValidationCapture::new(instance.clone());
 
         let debug_utils = DebugUtilsBuilder::new().build(instance.clone());
 
@@ -198,6 +475,7 @@ mod tests {
     #[test]
     fn test_debug_format() {
         let instance = create_instance();
+        let _validation = ValidationCapture::new(instance.clone());
 
         let debug_utils = DebugUtilsBuilder::new().build(instance.clone()).unwrap();
 
@@ -0,0 +1,157 @@
+use crate::gfx_debug_log;
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::sync::Arc;
+
+const INITIAL_SETS_PER_POOL: u32 = 64;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// Descriptors reserved per set for each type. Multiplied by the pool's set count.
+const DEFAULT_POOL_RATIOS: [(vk::DescriptorType, f32); 6] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+    (vk::DescriptorType::STORAGE_BUFFER, 1.0),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 1.0),
+    (vk::DescriptorType::SAMPLER, 1.0),
+    (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+];
+
+fn next_sets_per_pool(sets_per_pool: u32) -> u32 {
+    (sets_per_pool * 2).min(MAX_SETS_PER_POOL)
+}
+
+/// Allocates descriptor sets from a growing list of pools.
+/// Sets are never freed one by one; [`DescriptorAllocator::reset`] recycles all of them at once,
+/// so keep one allocator per frame in flight and reset it when that frame's work has finished.
+#[derive(Debug)]
+pub struct DescriptorAllocator {
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    sets_per_pool: u32,
+    current_pool: Option<vk::DescriptorPool>,
+    full_pools: Vec<vk::DescriptorPool>,
+    free_pools: Vec<vk::DescriptorPool>,
+    device: Arc<Device>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self::with_ratios(device, DEFAULT_POOL_RATIOS.to_vec())
+    }
+
+    pub fn with_ratios(device: Arc<Device>, ratios: Vec<(vk::DescriptorType, f32)>) -> Self {
+        Self {
+            ratios,
+            sets_per_pool: INITIAL_SETS_PER_POOL,
+            current_pool: None,
+            full_pools: vec![],
+            free_pools: vec![],
+            device,
+        }
+    }
+
+    pub fn allocate(&mut self, layout: vk::DescriptorSetLayout) -> VkResult<vk::DescriptorSet> {
+        let pool = self.current_pool()?;
+
+        match allocate_set(&self.device, pool, layout) {
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.full_pools.push(pool);
+                self.current_pool = None;
+
+                let pool = self.current_pool()?;
+                allocate_set(&self.device, pool, layout)
+            }
+            result => result,
+        }
+    }
+
+    /// Returns every set to its pool. Sets allocated before must no longer be in use by the GPU.
+    pub fn reset(&mut self) -> VkResult<()> {
+        let pools = self
+            .current_pool
+            .take()
+            .into_iter()
+            .chain(self.full_pools.drain(..));
+
+        for pool in pools {
+            unsafe {
+                self.device
+                    .handle()
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?;
+            }
+            self.free_pools.push(pool);
+        }
+
+        Ok(())
+    }
+
+    fn current_pool(&mut self) -> VkResult<vk::DescriptorPool> {
+        if let Some(pool) = self.current_pool {
+            return Ok(pool);
+        }
+
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => {
+                let pool = self.create_pool(self.sets_per_pool)?;
+                self.sets_per_pool = next_sets_per_pool(self.sets_per_pool);
+                pool
+            }
+        };
+
+        self.current_pool = Some(pool);
+
+        Ok(pool)
+    }
+
+    fn create_pool(&self, max_sets: u32) -> VkResult<vk::DescriptorPool> {
+        let pool_sizes: Vec<_> = self
+            .ratios
+            .iter()
+            .map(|(ty, ratio)| {
+                vk::DescriptorPoolSize::default()
+                    .ty(*ty)
+                    .descriptor_count(((max_sets as f32 * ratio) as u32).max(1))
+            })
+            .collect();
+
+        let create_info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(max_sets)
+            .pool_sizes(&pool_sizes);
+
+        self.device.create(&create_info)
+    }
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(DescriptorAllocator::drop()));
+        let pools = self
+            .current_pool
+            .take()
+            .into_iter()
+            .chain(self.full_pools.drain(..))
+            .chain(self.free_pools.drain(..));
+
+        for pool in pools {
+            self.device.destroy(pool);
+        }
+    }
+}
+
+fn allocate_set(
+    device: &Device,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+) -> VkResult<vk::DescriptorSet> {
+    let allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(pool)
+        .set_layouts(std::slice::from_ref(&layout));
+
+    unsafe {
+        device
+            .handle()
+            .allocate_descriptor_sets(&allocate_info)
+            .map(|sets| sets[0])
+    }
+}
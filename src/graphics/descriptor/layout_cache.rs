@@ -0,0 +1,109 @@
+use ash::vk;
+use std::collections::HashMap;
+
+/// Hashable description of one `vk::DescriptorSetLayoutBinding` without immutable samplers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct BindingKey {
+    binding: u32,
+    descriptor_type: i32,
+    count: u32,
+    stage_flags: u32,
+}
+
+impl From<&vk::DescriptorSetLayoutBinding<'_>> for BindingKey {
+    fn from(value: &vk::DescriptorSetLayoutBinding<'_>) -> Self {
+        Self {
+            binding: value.binding,
+            descriptor_type: value.descriptor_type.as_raw(),
+            count: value.descriptor_count,
+            stage_flags: value.stage_flags.as_raw(),
+        }
+    }
+}
+
+/// Binding list in a canonical order, so the same set of bindings always maps to one layout
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LayoutKey(Vec<BindingKey>);
+
+impl LayoutKey {
+    pub fn new(bindings: &[vk::DescriptorSetLayoutBinding<'_>]) -> Self {
+        let mut keys: Vec<_> = bindings.iter().map(BindingKey::from).collect();
+        keys.sort();
+
+        Self(keys)
+    }
+}
+
+/// Descriptor set layouts shared between all pipelines of a device.
+/// Layouts live as long as the device and are destroyed together with it.
+#[derive(Debug, Default)]
+pub struct DescriptorSetLayoutCache {
+    layouts: HashMap<LayoutKey, vk::DescriptorSetLayout>,
+}
+
+impl DescriptorSetLayoutCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &ash::Device,
+        bindings: &[vk::DescriptorSetLayoutBinding<'_>],
+    ) -> ash::prelude::VkResult<vk::DescriptorSetLayout> {
+        let key = LayoutKey::new(bindings);
+
+        if let Some(layout) = self.layouts.get(&key) {
+            return Ok(*layout);
+        }
+
+        let create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(bindings);
+        let layout = unsafe { device.create_descriptor_set_layout(&create_info, None)? };
+
+        self.layouts.insert(key, layout);
+
+        Ok(layout)
+    }
+
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.is_empty()
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (_, layout) in self.layouts.drain() {
+            unsafe { device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_key_ignores_binding_order() {
+        let uniform = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX);
+        let texture = vk::DescriptorSetLayoutBinding::default()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+        assert_eq!(
+            LayoutKey::new(&[uniform, texture]),
+            LayoutKey::new(&[texture, uniform])
+        );
+        assert_ne!(
+            LayoutKey::new(&[uniform]),
+            LayoutKey::new(&[uniform.stage_flags(vk::ShaderStageFlags::ALL_GRAPHICS)])
+        );
+    }
+}
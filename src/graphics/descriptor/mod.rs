@@ -0,0 +1,240 @@
+use crate::graphics::buffer::{Buffer, BufferSlice};
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use crate::graphics::texture::Image;
+use ash::prelude::VkResult;
+use ash::vk;
+
+pub mod allocator;
+pub mod layout_cache;
+
+pub trait DeviceDescriptorFns {
+    /// Returns the cached layout for `bindings`, creating it on first use
+    fn get_descriptor_set_layout(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding<'_>],
+    ) -> VkResult<vk::DescriptorSetLayout>;
+}
+
+impl DeviceDescriptorFns for Device {
+    fn get_descriptor_set_layout(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding<'_>],
+    ) -> VkResult<vk::DescriptorSetLayout> {
+        self.descriptor_layouts()
+            .get_or_create(&self.handle(), bindings)
+    }
+}
+
+#[derive(Debug)]
+enum DescriptorInfo {
+    Image(vk::DescriptorImageInfo),
+    Buffer(vk::DescriptorBufferInfo),
+}
+
+#[derive(Debug)]
+struct PendingWrite {
+    binding: u32,
+    array_element: u32,
+    descriptor_type: vk::DescriptorType,
+    info: DescriptorInfo,
+}
+
+/// Collects descriptor writes and applies them to a set in one `vkUpdateDescriptorSets` call
+#[derive(Debug, Default)]
+pub struct DescriptorWriter {
+    writes: Vec<PendingWrite>,
+}
+
+impl DescriptorWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds the image in `SHADER_READ_ONLY_OPTIMAL` layout. Images with a sampler are bound
+    /// as `COMBINED_IMAGE_SAMPLER`, others as `SAMPLED_IMAGE`.
+    pub fn sampled_image(self, binding: u32, image: &Image) -> Self {
+        let descriptor_type = match image.sampler() {
+            Some(_) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            None => vk::DescriptorType::SAMPLED_IMAGE,
+        };
+
+        self.image(
+            binding,
+            descriptor_type,
+            image.image_view(),
+            image.sampler().unwrap_or_default(),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+
+    /// Binds the image as `STORAGE_IMAGE` in `GENERAL` layout
+    pub fn storage_image(self, binding: u32, image: &Image) -> Self {
+        self.image(
+            binding,
+            vk::DescriptorType::STORAGE_IMAGE,
+            image.image_view(),
+            vk::Sampler::null(),
+            vk::ImageLayout::GENERAL,
+        )
+    }
+
+    pub fn sampler(self, binding: u32, sampler: vk::Sampler) -> Self {
+        self.image(
+            binding,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            sampler,
+            vk::ImageLayout::UNDEFINED,
+        )
+    }
+
+    pub fn image(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        image_layout: vk::ImageLayout,
+    ) -> Self {
+        let info = vk::DescriptorImageInfo::default()
+            .image_view(image_view)
+            .sampler(sampler)
+            .image_layout(image_layout);
+
+        self.push(binding, descriptor_type, DescriptorInfo::Image(info));
+        self
+    }
+
+    pub fn uniform_buffer(self, binding: u32, buffer: &Buffer) -> Self {
+        self.buffer(binding, vk::DescriptorType::UNIFORM_BUFFER, buffer)
+    }
+
+    pub fn storage_buffer(self, binding: u32, buffer: &Buffer) -> Self {
+        self.buffer(binding, vk::DescriptorType::STORAGE_BUFFER, buffer)
+    }
+
+    /// Binds the whole buffer
+    pub fn buffer(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: &Buffer,
+    ) -> Self {
+        self.buffer_range(binding, descriptor_type, buffer.handle(), 0, vk::WHOLE_SIZE)
+    }
+
+    pub fn buffer_slice<T>(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        slice: BufferSlice<'_, T>,
+    ) -> Self {
+        self.buffer_range(
+            binding,
+            descriptor_type,
+            slice.handle(),
+            slice.offset(),
+            slice.size(),
+        )
+    }
+
+    pub fn buffer_range(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    ) -> Self {
+        let info = vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(offset)
+            .range(range);
+
+        self.push(binding, descriptor_type, DescriptorInfo::Buffer(info));
+        self
+    }
+
+    /// Writes to the same binding are placed in consecutive array elements
+    fn push(&mut self, binding: u32, descriptor_type: vk::DescriptorType, info: DescriptorInfo) {
+        let array_element = self
+            .writes
+            .iter()
+            .filter(|write| write.binding == binding)
+            .count() as u32;
+
+        self.writes.push(PendingWrite {
+            binding,
+            array_element,
+            descriptor_type,
+            info,
+        });
+    }
+
+    pub fn update(&self, device: &Device, set: vk::DescriptorSet) {
+        let writes: Vec<_> = self
+            .writes
+            .iter()
+            .map(|write| {
+                let vk_write = vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(write.binding)
+                    .dst_array_element(write.array_element)
+                    .descriptor_type(write.descriptor_type);
+
+                match &write.info {
+                    DescriptorInfo::Image(info) => vk_write.image_info(std::slice::from_ref(info)),
+                    DescriptorInfo::Buffer(info) => {
+                        vk_write.buffer_info(std::slice::from_ref(info))
+                    }
+                }
+            })
+            .collect();
+
+        unsafe { device.handle().update_descriptor_sets(&writes, &[]) };
+    }
+}
+
+impl DeviceCreateExtend<vk::DescriptorPoolCreateInfo<'_>, vk::DescriptorPool> for Device {
+    fn create(
+        &self,
+        create_info: &vk::DescriptorPoolCreateInfo<'_>,
+    ) -> VkResult<vk::DescriptorPool> {
+        unsafe { self.handle().create_descriptor_pool(create_info, None) }
+    }
+}
+
+impl DeviceDestroyExtend<vk::DescriptorPool> for Device {
+    fn destroy(&self, vk_struct: vk::DescriptorPool) {
+        unsafe {
+            self.handle().destroy_descriptor_pool(vk_struct, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_array_elements() {
+        let writer = DescriptorWriter::new()
+            .sampler(0, vk::Sampler::null())
+            .sampler(0, vk::Sampler::null())
+            .buffer_range(
+                1,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::Buffer::null(),
+                0,
+                64,
+            );
+
+        let elements: Vec<_> = writer
+            .writes
+            .iter()
+            .map(|write| (write.binding, write.array_element))
+            .collect();
+
+        assert_eq!(elements, vec![(0, 0), (0, 1), (1, 0)]);
+    }
+}
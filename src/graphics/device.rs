@@ -1,38 +1,100 @@
 use ash::prelude::VkResult;
+use std::collections::VecDeque;
 use std::fmt::{Debug, Formatter};
-use std::rc::Rc;
-
-use crate::gfx_debug_log;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
+use crate::graphics::descriptor::layout_cache::DescriptorSetLayoutCache;
+use crate::graphics::memory::allocator::Allocator;
+use crate::graphics::pipeline::cache::{
+    default_pipeline_cache_dir, PipelineCache, PipelineCacheError,
+};
 use crate::graphics::surface::Surface;
+use crate::graphics::sync::timeline::Timeline;
+use crate::{gfx_debug_exec, gfx_debug_log};
 use ash::vk;
 
 use super::instance::Instance;
 
 pub struct Device {
     handle: ash::Device,
-    _instance: Rc<Instance>,
+    _instance: Arc<Instance>,
     swapchain_fns: ash::khr::swapchain::Device,
     _dynamic_rendering_fns: ash::khr::dynamic_rendering::Device,
+    /// Only loaded when the instance enables `VK_EXT_debug_utils`
+    #[cfg(feature = "gfx_debug_msg")]
+    debug_utils_fns: Option<ash::ext::debug_utils::Device>,
     physical_device: vk::PhysicalDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    allocator: Mutex<Allocator>,
+    descriptor_layouts: Mutex<DescriptorSetLayoutCache>,
+    pipeline_cache: PipelineCache,
+    deferred: Mutex<DeferredDestroys>,
 }
 
 impl Device {
     fn new(
         handle: ash::Device,
-        instance: Rc<Instance>,
+        instance: Arc<Instance>,
         swapchain_fns: ash::khr::swapchain::Device,
         dynamic_rendering_fns: ash::khr::dynamic_rendering::Device,
         physical_device: vk::PhysicalDevice,
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        pipeline_cache: PipelineCache,
     ) -> Self {
+        #[cfg(feature = "gfx_debug_msg")]
+        let debug_utils_fns = instance
+            .has_debug_utils()
+            .then(|| ash::ext::debug_utils::Device::new(&instance.handle(), &handle));
+
         Self {
             handle,
             _instance: instance,
             swapchain_fns,
             _dynamic_rendering_fns: dynamic_rendering_fns,
+            #[cfg(feature = "gfx_debug_msg")]
+            debug_utils_fns,
             physical_device,
+            memory_properties,
+            allocator: Mutex::new(Allocator::new(memory_properties)),
+            descriptor_layouts: Mutex::new(DescriptorSetLayoutCache::new()),
+            pipeline_cache,
+            deferred: Mutex::new(DeferredDestroys::default()),
         }
     }
 
+    #[cfg(feature = "gfx_debug_msg")]
+    pub(crate) fn debug_utils_fns(&self) -> Option<&ash::ext::debug_utils::Device> {
+        self.debug_utils_fns.as_ref()
+    }
+
+    #[cfg(not(feature = "gfx_debug_msg"))]
+    pub(crate) fn debug_utils_fns(&self) -> Option<&ash::ext::debug_utils::Device> {
+        None
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    pub(crate) fn allocator(&self) -> MutexGuard<'_, Allocator> {
+        self.allocator.lock().unwrap()
+    }
+
+    pub(crate) fn descriptor_layouts(&self) -> MutexGuard<'_, DescriptorSetLayoutCache> {
+        self.descriptor_layouts.lock().unwrap()
+    }
+
+    pub fn pipeline_cache(&self) -> vk::PipelineCache {
+        self.pipeline_cache.handle()
+    }
+
+    /// Writes the pipeline cache to disk. Also done when the device is dropped.
+    pub fn save_pipeline_cache(&self) -> Result<(), PipelineCacheError> {
+        self.pipeline_cache.save(&self.handle)
+    }
+
     pub fn default_extensions() -> Vec<String> {
         vec![
             "VK_KHR_swapchain".to_owned(),
@@ -40,22 +102,22 @@ impl Device {
         ]
     }
 
-    pub fn get_surface_capabilities(&self, surface: &Surface) -> vk::SurfaceCapabilitiesKHR {
-        surface
-            .get_physical_device_surface_capabilities(self.physical_device)
-            .unwrap()
+    pub fn get_surface_capabilities(
+        &self,
+        surface: &Surface,
+    ) -> VkResult<vk::SurfaceCapabilitiesKHR> {
+        surface.get_physical_device_surface_capabilities(self.physical_device)
     }
 
-    pub fn get_surface_formats(&self, surface: &Surface) -> Vec<vk::SurfaceFormatKHR> {
-        surface
-            .get_physical_device_surface_formats(self.physical_device)
-            .unwrap()
+    pub fn get_surface_formats(&self, surface: &Surface) -> VkResult<Vec<vk::SurfaceFormatKHR>> {
+        surface.get_physical_device_surface_formats(self.physical_device)
     }
 
-    pub fn get_surface_present_modes(&self, surface: &Surface) -> Vec<vk::PresentModeKHR> {
-        surface
-            .get_physical_device_surface_present_modes(self.physical_device)
-            .unwrap()
+    pub fn get_surface_present_modes(
+        &self,
+        surface: &Surface,
+    ) -> VkResult<Vec<vk::PresentModeKHR>> {
+        surface.get_physical_device_surface_present_modes(self.physical_device)
     }
 
     pub fn get_swapchain_images(&self, swapchain: vk::SwapchainKHR) -> VkResult<Vec<vk::Image>> {
@@ -65,6 +127,64 @@ impl Device {
     pub fn wait_idle(&self) -> VkResult<()> {
         unsafe { self.handle.device_wait_idle() }
     }
+
+    /// Runs `destroy` once the frame being recorded and every frame before it have retired.
+    /// `destroy` must not hold the device.
+    pub fn defer_destroy(&self, destroy: impl FnOnce(&Device) + Send + 'static) {
+        self.deferred.lock().unwrap().push(Box::new(destroy));
+    }
+
+    pub fn pending_destroys(&self) -> usize {
+        self.deferred.lock().unwrap().pending.len()
+    }
+
+    /// Ends the frame being recorded and returns its number
+    pub(crate) fn end_frame(&self) -> u64 {
+        self.deferred.lock().unwrap().end_frame()
+    }
+
+    /// Destroys the objects dropped up to `frame`, the GPU has to be done with it
+    pub(crate) fn retire_frames(&self, frame: u64) {
+        // Destroys can drop objects that defer again, so the queue is not locked while they run
+        let retired = self.deferred.lock().unwrap().take_retired(frame);
+        for destroy in retired {
+            destroy(self);
+        }
+    }
+}
+
+type DestroyFn = Box<dyn FnOnce(&Device) + Send>;
+
+/// Destruction of dropped objects that frames in flight may still use
+#[derive(Default)]
+struct DeferredDestroys {
+    /// Frame being recorded, objects dropped now are tagged with it
+    frame: u64,
+    pending: VecDeque<(u64, DestroyFn)>,
+}
+
+impl DeferredDestroys {
+    fn push(&mut self, destroy: DestroyFn) {
+        self.pending.push_back((self.frame, destroy));
+    }
+
+    fn end_frame(&mut self) -> u64 {
+        self.frame += 1;
+        self.frame - 1
+    }
+
+    fn take_retired(&mut self, frame: u64) -> Vec<DestroyFn> {
+        let count = self
+            .pending
+            .iter()
+            .take_while(|(tag, _)| *tag <= frame)
+            .count();
+
+        self.pending
+            .drain(..count)
+            .map(|(_, destroy)| destroy)
+            .collect()
+    }
 }
 
 impl Debug for Device {
@@ -80,8 +200,19 @@ impl Debug for Device {
 impl Drop for Device {
     fn drop(&mut self) {
         gfx_debug_log!(stringify!(Device::drop()));
+        // Owners of submissions wait for them before dropping their device handle,
+        // e.g. `FrameRing` for the frames in flight
+        self.retire_frames(u64::MAX);
         unsafe {
-            self.wait_idle().unwrap();
+            if let Err(e) = self.pipeline_cache.save(&self.handle) {
+                log::warn!(target: "rust_engine::graphics", "Can't save pipeline cache: {e}");
+            }
+            self.pipeline_cache.destroy(&self.handle);
+            self.descriptor_layouts
+                .get_mut()
+                .unwrap()
+                .destroy(&self.handle);
+            self.allocator.get_mut().unwrap().destroy(&self.handle);
             self.handle.destroy_device(None);
         }
     }
@@ -109,22 +240,40 @@ pub trait DeviceDestroyExtend<T>: VulkanDevice {
     fn destroy(&self, vk_struct: T);
 }
 
+/// Clones share the lock that externally synchronizes submission, see [`Queue::lock`]
 #[derive(Debug, Clone)]
 pub struct Queue {
     handle: vk::Queue,
-    device: Rc<Device>,
+    device: Arc<Device>,
     family_index: u32,
     index: u32,
+    timeline: Arc<Timeline>,
+    lock: Arc<Mutex<()>>,
 }
 
 impl Queue {
-    fn new(handle: vk::Queue, device: Rc<Device>, family_index: u32, index: u32) -> Self {
-        Self {
+    fn new(
+        handle: vk::Queue,
+        device: Arc<Device>,
+        family_index: u32,
+        index: u32,
+    ) -> VkResult<Self> {
+        let timeline = Arc::new(Timeline::new(device.clone())?);
+
+        gfx_debug_exec!({
+            let name = format!("queue {family_index}.{index}");
+            device.set_object_name(handle, &name);
+            timeline.set_object_name(&format!("{name} timeline"));
+        });
+
+        Ok(Self {
             handle,
             device,
             family_index,
             index,
-        }
+            timeline,
+            lock: Arc::new(Mutex::new(())),
+        })
     }
 
     pub fn handle(&self) -> vk::Queue {
@@ -135,19 +284,46 @@ impl Queue {
         self.family_index
     }
 
+    /// Timeline signaled by tasks submitted to this queue
+    pub fn timeline(&self) -> &Arc<Timeline> {
+        &self.timeline
+    }
+
+    /// Locks the queue for submission. Timeline values have to be reserved while the lock
+    /// is held, otherwise another thread could signal a later value first.
+    pub fn lock(&self) -> QueueGuard<'_> {
+        QueueGuard {
+            queue: self,
+            _guard: self.lock.lock().unwrap(),
+        }
+    }
+}
+
+pub struct QueueGuard<'a> {
+    queue: &'a Queue,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl QueueGuard<'_> {
+    pub fn queue(&self) -> &Queue {
+        self.queue
+    }
+
     pub fn submit(&self, submits: &[vk::SubmitInfo<'_>], fence: vk::Fence) -> VkResult<()> {
         unsafe {
-            self.device
+            self.queue
+                .device
                 .handle()
-                .queue_submit(self.handle, submits, fence)
+                .queue_submit(self.queue.handle, submits, fence)
         }
     }
 
     pub fn present(&self, present_info: vk::PresentInfoKHR<'_>) -> VkResult<bool> {
         unsafe {
-            self.device
+            self.queue
+                .device
                 .swapchain_fns()
-                .queue_present(self.handle, &present_info)
+                .queue_present(self.queue.handle, &present_info)
         }
     }
 }
@@ -155,11 +331,11 @@ impl Queue {
 #[derive(Debug)]
 pub struct PhysicalDevice {
     handle: vk::PhysicalDevice,
-    instance: Rc<Instance>,
+    instance: Arc<Instance>,
 }
 
 impl PhysicalDevice {
-    pub fn new(handle: vk::PhysicalDevice, instance: Rc<Instance>) -> Self {
+    pub fn new(handle: vk::PhysicalDevice, instance: Arc<Instance>) -> Self {
         Self { handle, instance }
     }
 
@@ -179,6 +355,29 @@ impl PhysicalDevice {
     pub fn get_features(&self) -> vk::PhysicalDeviceFeatures {
         self.instance.get_physical_device_features(self.handle)
     }
+
+    pub fn get_memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
+        self.instance
+            .get_physical_device_memory_properties(self.handle)
+    }
+
+    pub fn get_uuid(&self) -> [u8; vk::UUID_SIZE] {
+        self.instance
+            .get_physical_device_id_properties(self.handle)
+            .device_uuid
+    }
+
+    pub fn get_extension_names(&self) -> VkResult<Vec<String>> {
+        let properties = self
+            .instance
+            .enumerate_device_extension_properties(self.handle)?;
+
+        Ok(properties
+            .iter()
+            .filter_map(|extension| extension.extension_name_as_c_str().ok())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
 }
 
 pub struct DeviceBuilder<'a> {
@@ -186,6 +385,7 @@ pub struct DeviceBuilder<'a> {
     pub features: vk::PhysicalDeviceFeatures,
     pub extends: Vec<Box<dyn vk::ExtendsDeviceCreateInfo + 'a>>,
     pub queues: Vec<QueueDescription>,
+    pub pipeline_cache_dir: Option<PathBuf>,
 }
 
 impl<'a> Default for DeviceBuilder<'a> {
@@ -195,6 +395,7 @@ impl<'a> Default for DeviceBuilder<'a> {
             features: vk::PhysicalDeviceFeatures::default(),
             extends: Vec::default(),
             queues: Vec::default(),
+            pipeline_cache_dir: Some(default_pipeline_cache_dir()),
         }
     }
 }
@@ -219,11 +420,17 @@ impl<'a> DeviceBuilder<'a> {
         self
     }
 
+    /// Directory for the persistent pipeline cache, `None` keeps the cache in memory only
+    pub fn pipeline_cache_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.pipeline_cache_dir = dir;
+        self
+    }
+
     pub fn build(
         self,
-        instance: Rc<Instance>,
+        instance: Arc<Instance>,
         physical_device: PhysicalDevice,
-    ) -> VkResult<(Rc<Device>, impl ExactSizeIterator<Item = Queue>)> {
+    ) -> VkResult<(Arc<Device>, impl ExactSizeIterator<Item = Queue>)> {
         let extensions: Vec<_> = self
             .extensions
             .into_iter()
@@ -242,12 +449,17 @@ impl<'a> DeviceBuilder<'a> {
             })
             .collect();
 
+        // Every queue tracks its submissions with a timeline semaphore
+        let mut timeline_features =
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true);
+
         let mut extends = self.extends;
         let device_create_info = {
             let mut device_create_info = vk::DeviceCreateInfo::default()
                 .queue_create_infos(&queue_create_infos)
                 .enabled_extension_names(&extensions_raw)
-                .enabled_features(&self.features);
+                .enabled_features(&self.features)
+                .push_next(&mut timeline_features);
 
             for x in extends.iter_mut() {
                 device_create_info = device_create_info.push_next(x.as_mut());
@@ -261,12 +473,26 @@ impl<'a> DeviceBuilder<'a> {
             let swapchain_fns = ash::khr::swapchain::Device::new(&instance.handle(), &device);
             let dynamic_rendering_fns =
                 ash::khr::dynamic_rendering::Device::new(&instance.handle(), &device);
-            Rc::new(Device::new(
+            let memory_properties = physical_device.get_memory_properties();
+            let pipeline_cache = match PipelineCache::load(
+                &device,
+                &physical_device.get_properties(),
+                self.pipeline_cache_dir.as_deref(),
+            ) {
+                Ok(pipeline_cache) => pipeline_cache,
+                Err(e) => {
+                    unsafe { device.destroy_device(None) };
+                    return Err(e);
+                }
+            };
+            Arc::new(Device::new(
                 device,
                 instance,
                 swapchain_fns,
                 dynamic_rendering_fns,
                 physical_device.handle,
+                memory_properties,
+                pipeline_cache,
             ))
         };
 
@@ -286,15 +512,15 @@ impl<'a> DeviceBuilder<'a> {
             queue_infos
         };
 
-        let queues = {
-            let device_for_iter = device.clone();
-            queue_infos.into_iter().map(move |(family, index)| {
-                let queue = unsafe { device_for_iter.handle.get_device_queue(family, index) };
-                Queue::new(queue, device_for_iter.clone(), family, index)
+        let queues = queue_infos
+            .into_iter()
+            .map(|(family, index)| {
+                let queue = unsafe { device.handle.get_device_queue(family, index) };
+                Queue::new(queue, device.clone(), family, index)
             })
-        };
+            .collect::<VkResult<Vec<_>>>()?;
 
-        Ok((device, queues))
+        Ok((device, queues.into_iter()))
     }
 }
 
@@ -330,3 +556,24 @@ impl DeviceExtensionPush for DeviceBuilder<'_> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deferred_destroys_retire_in_frame_order() {
+        let mut deferred = DeferredDestroys::default();
+
+        deferred.push(Box::new(|_| {}));
+        assert_eq!(deferred.end_frame(), 0);
+        deferred.push(Box::new(|_| {}));
+        deferred.push(Box::new(|_| {}));
+        assert_eq!(deferred.end_frame(), 1);
+
+        assert_eq!(deferred.take_retired(0).len(), 1);
+        assert_eq!(deferred.take_retired(0).len(), 0);
+        assert_eq!(deferred.take_retired(1).len(), 2);
+        assert!(deferred.pending.is_empty());
+    }
+}
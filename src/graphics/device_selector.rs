@@ -0,0 +1,517 @@
+use super::device::PhysicalDevice;
+use super::surface::Surface;
+use ash::prelude::VkResult;
+use ash::vk;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Environment variable that forces a GPU, by index, name or UUID
+pub const GPU_ENV_VAR: &str = "LPPS_GPU";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceOverride {
+    Index(usize),
+    /// Case insensitive part of the device name
+    Name(String),
+    Uuid([u8; vk::UUID_SIZE]),
+}
+
+impl DeviceOverride {
+    /// Numbers select by index, 32 hex digits (dashes allowed) by UUID, anything else by name
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+
+        if let Ok(index) = value.parse() {
+            return DeviceOverride::Index(index);
+        }
+
+        let hex: String = value.chars().filter(|c| *c != '-').collect();
+        if hex.len() == vk::UUID_SIZE * 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut uuid = [0; vk::UUID_SIZE];
+            for (index, byte) in uuid.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).unwrap();
+            }
+
+            return DeviceOverride::Uuid(uuid);
+        }
+
+        DeviceOverride::Name(value.to_owned())
+    }
+
+    pub fn from_env() -> Option<Self> {
+        std::env::var(GPU_ENV_VAR)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| Self::parse(&value))
+    }
+
+    fn matches(&self, candidate: &DeviceCandidate) -> bool {
+        match self {
+            DeviceOverride::Index(index) => candidate.index == *index,
+            DeviceOverride::Name(name) => {
+                candidate.name.to_lowercase().contains(&name.to_lowercase())
+            }
+            DeviceOverride::Uuid(uuid) => candidate.uuid == *uuid,
+        }
+    }
+}
+
+impl Display for DeviceOverride {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceOverride::Index(index) => write!(f, "index {index}"),
+            DeviceOverride::Name(name) => write!(f, "name \"{name}\""),
+            DeviceOverride::Uuid(uuid) => write!(f, "UUID {}", format_uuid(uuid)),
+        }
+    }
+}
+
+/// What the selector needs to know about a physical device
+#[derive(Debug, Clone, Default)]
+pub struct DeviceCandidate {
+    pub index: usize,
+    pub name: String,
+    pub uuid: [u8; vk::UUID_SIZE],
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    pub extensions: Vec<String>,
+    pub features: vk::PhysicalDeviceFeatures,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+    /// Present support of each queue family, all `true` without a surface
+    pub present_support: Vec<bool>,
+    pub device_local_memory: u64,
+}
+
+impl DeviceCandidate {
+    pub fn query(
+        index: usize,
+        physical_device: &PhysicalDevice,
+        surface: Option<&Surface>,
+    ) -> VkResult<Self> {
+        let properties = physical_device.get_properties();
+        let queue_families = physical_device.get_queue_family_properties();
+
+        let present_support = (0..queue_families.len() as u32)
+            .map(|family| {
+                surface.is_none_or(|surface| {
+                    surface
+                        .get_physical_device_surface_support(physical_device, family)
+                        .unwrap_or(false)
+                })
+            })
+            .collect();
+
+        let memory = physical_device.get_memory_properties();
+        let device_local_memory = memory
+            .memory_heaps_as_slice()
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
+
+        Ok(Self {
+            index,
+            name: properties
+                .device_name_as_c_str()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            uuid: physical_device.get_uuid(),
+            device_type: properties.device_type,
+            api_version: properties.api_version,
+            extensions: physical_device.get_extension_names()?,
+            features: physical_device.get_features(),
+            queue_families,
+            present_support,
+            device_local_memory,
+        })
+    }
+
+    /// First queue family that supports graphics and presentation
+    pub fn graphics_queue_family(&self) -> Option<u32> {
+        self.queue_families
+            .iter()
+            .zip(self.present_support.iter())
+            .position(|(family, present)| {
+                family.queue_flags.contains(vk::QueueFlags::GRAPHICS) && *present
+            })
+            .map(|family| family as u32)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    ApiVersion { required: u32, found: u32 },
+    MissingExtensions(Vec<String>),
+    MissingFeatures(Vec<&'static str>),
+    NoGraphicsQueue,
+    NoPresentQueue,
+    NotEnoughMemory { required: u64, found: u64 },
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let version = |version: &u32| {
+            format!(
+                "{}.{}",
+                vk::api_version_major(*version),
+                vk::api_version_minor(*version)
+            )
+        };
+
+        match self {
+            Rejection::ApiVersion { required, found } => write!(
+                f,
+                "supports Vulkan {}, {} is required",
+                version(found),
+                version(required)
+            ),
+            Rejection::MissingExtensions(extensions) => {
+                write!(f, "missing extensions {}", extensions.join(", "))
+            }
+            Rejection::MissingFeatures(features) => {
+                write!(f, "missing features {}", features.join(", "))
+            }
+            Rejection::NoGraphicsQueue => write!(f, "no graphics queue"),
+            Rejection::NoPresentQueue => write!(f, "no graphics queue can present to the surface"),
+            Rejection::NotEnoughMemory { required, found } => write!(
+                f,
+                "{} MiB of device local memory, {} MiB is required",
+                found >> 20,
+                required >> 20
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DeviceSelectionError {
+    NoDevices,
+    /// The override from [`GPU_ENV_VAR`] matched none of the devices
+    OverrideNotFound {
+        device_override: DeviceOverride,
+        devices: Vec<String>,
+    },
+    /// Every device was rejected, with the reasons for each one
+    NoSuitableDevice(Vec<(String, Vec<Rejection>)>),
+}
+
+impl Display for DeviceSelectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceSelectionError::NoDevices => write!(f, "no Vulkan devices found"),
+            DeviceSelectionError::OverrideNotFound {
+                device_override,
+                devices,
+            } => write!(
+                f,
+                "{GPU_ENV_VAR} selects {device_override}, available devices: {}",
+                devices.join(", ")
+            ),
+            DeviceSelectionError::NoSuitableDevice(rejected) => {
+                write!(f, "no suitable device:")?;
+                for (name, reasons) in rejected {
+                    let reasons: Vec<_> = reasons.iter().map(|reason| reason.to_string()).collect();
+                    write!(f, "\n  {name}: {}", reasons.join("; "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for DeviceSelectionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceSelection {
+    pub index: usize,
+    pub queue_family_index: u32,
+    pub score: u64,
+}
+
+type FeatureCheck = fn(&vk::PhysicalDeviceFeatures) -> bool;
+
+/// Picks the physical device to render with. Devices missing a requirement are rejected,
+/// the others are scored on device type, memory size and queue families.
+#[derive(Debug, Clone)]
+pub struct DeviceSelector {
+    pub api_version: u32,
+    pub extensions: Vec<String>,
+    pub features: Vec<(&'static str, FeatureCheck)>,
+    pub min_memory: u64,
+    pub device_override: Option<DeviceOverride>,
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        Self {
+            api_version: vk::API_VERSION_1_0,
+            extensions: vec![],
+            features: vec![],
+            min_memory: 0,
+            device_override: None,
+        }
+    }
+}
+
+impl DeviceSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn api_version(mut self, api_version: u32) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    pub fn extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Requires a core feature, `name` is used to explain rejections
+    pub fn require_feature(mut self, name: &'static str, check: FeatureCheck) -> Self {
+        self.features.push((name, check));
+        self
+    }
+
+    pub fn min_memory(mut self, min_memory: u64) -> Self {
+        self.min_memory = min_memory;
+        self
+    }
+
+    pub fn device_override(mut self, device_override: Option<DeviceOverride>) -> Self {
+        self.device_override = device_override;
+        self
+    }
+
+    /// Reasons the device can't be used, empty if it is suitable
+    pub fn check(&self, candidate: &DeviceCandidate) -> Vec<Rejection> {
+        let mut rejections = vec![];
+
+        if candidate.api_version < self.api_version {
+            rejections.push(Rejection::ApiVersion {
+                required: self.api_version,
+                found: candidate.api_version,
+            });
+        }
+
+        let missing_extensions: Vec<_> = self
+            .extensions
+            .iter()
+            .filter(|extension| !candidate.extensions.contains(extension))
+            .cloned()
+            .collect();
+        if !missing_extensions.is_empty() {
+            rejections.push(Rejection::MissingExtensions(missing_extensions));
+        }
+
+        let missing_features: Vec<_> = self
+            .features
+            .iter()
+            .filter(|(_, check)| !check(&candidate.features))
+            .map(|(name, _)| *name)
+            .collect();
+        if !missing_features.is_empty() {
+            rejections.push(Rejection::MissingFeatures(missing_features));
+        }
+
+        let has_graphics = candidate
+            .queue_families
+            .iter()
+            .any(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+        if !has_graphics {
+            rejections.push(Rejection::NoGraphicsQueue);
+        } else if candidate.graphics_queue_family().is_none() {
+            rejections.push(Rejection::NoPresentQueue);
+        }
+
+        if candidate.device_local_memory < self.min_memory {
+            rejections.push(Rejection::NotEnoughMemory {
+                required: self.min_memory,
+                found: candidate.device_local_memory,
+            });
+        }
+
+        rejections
+    }
+
+    pub fn score(&self, candidate: &DeviceCandidate) -> u64 {
+        let type_score = match candidate.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 10_000,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 5_000,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2_000,
+            vk::PhysicalDeviceType::CPU => 1_000,
+            _ => 0,
+        };
+
+        // Queue families without graphics allow async compute and transfers
+        let queue_score = candidate
+            .queue_families
+            .iter()
+            .filter(|family| !family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .count() as u64
+            * 100;
+
+        let memory_score = candidate.device_local_memory >> 20 >> 6;
+
+        type_score + queue_score + memory_score
+    }
+
+    pub fn select(
+        &self,
+        candidates: &[DeviceCandidate],
+    ) -> Result<DeviceSelection, DeviceSelectionError> {
+        if candidates.is_empty() {
+            return Err(DeviceSelectionError::NoDevices);
+        }
+
+        let considered: Vec<_> = match &self.device_override {
+            Some(device_override) => candidates
+                .iter()
+                .filter(|candidate| device_override.matches(candidate))
+                .collect(),
+            None => candidates.iter().collect(),
+        };
+
+        if let (Some(device_override), true) = (&self.device_override, considered.is_empty()) {
+            return Err(DeviceSelectionError::OverrideNotFound {
+                device_override: device_override.clone(),
+                devices: candidates
+                    .iter()
+                    .map(|candidate| {
+                        format!(
+                            "{} {} ({})",
+                            candidate.index,
+                            candidate.name,
+                            format_uuid(&candidate.uuid)
+                        )
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut rejected = vec![];
+        let mut best: Option<DeviceSelection> = None;
+
+        for candidate in considered {
+            let rejections = self.check(candidate);
+            if !rejections.is_empty() {
+                rejected.push((candidate.name.clone(), rejections));
+                continue;
+            }
+
+            let selection = DeviceSelection {
+                index: candidate.index,
+                queue_family_index: candidate.graphics_queue_family().unwrap(),
+                score: self.score(candidate),
+            };
+
+            if best.is_none_or(|best| selection.score > best.score) {
+                best = Some(selection);
+            }
+        }
+
+        for (name, rejections) in rejected.iter() {
+            let reasons: Vec<_> = rejections.iter().map(|reason| reason.to_string()).collect();
+            log::info!(target: "rust_engine::graphics", "Skipping {name}: {}", reasons.join("; "));
+        }
+
+        best.ok_or(DeviceSelectionError::NoSuitableDevice(rejected))
+    }
+}
+
+fn format_uuid(uuid: &[u8; vk::UUID_SIZE]) -> String {
+    uuid.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(index: usize, name: &str, device_type: vk::PhysicalDeviceType) -> DeviceCandidate {
+        DeviceCandidate {
+            index,
+            name: name.to_owned(),
+            uuid: [index as u8; vk::UUID_SIZE],
+            device_type,
+            api_version: vk::API_VERSION_1_3,
+            extensions: vec!["VK_KHR_swapchain".to_owned()],
+            features: vk::PhysicalDeviceFeatures::default().sampler_anisotropy(true),
+            queue_families: vec![vk::QueueFamilyProperties {
+                queue_flags: vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+                queue_count: 1,
+                ..Default::default()
+            }],
+            present_support: vec![true],
+            device_local_memory: 4 << 30,
+        }
+    }
+
+    fn selector() -> DeviceSelector {
+        DeviceSelector::new()
+            .api_version(vk::API_VERSION_1_3)
+            .extensions(vec!["VK_KHR_swapchain".to_owned()])
+            .require_feature("samplerAnisotropy", |features| {
+                features.sampler_anisotropy == vk::TRUE
+            })
+    }
+
+    #[test]
+    fn test_override_parse() {
+        assert_eq!(DeviceOverride::parse("1"), DeviceOverride::Index(1));
+        assert_eq!(
+            DeviceOverride::parse("0101010101010101-0101010101010101"),
+            DeviceOverride::Uuid([1; vk::UUID_SIZE])
+        );
+        assert_eq!(
+            DeviceOverride::parse(" llvmpipe "),
+            DeviceOverride::Name("llvmpipe".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_select_scores_and_rejections() {
+        let integrated = candidate(0, "Integrated", vk::PhysicalDeviceType::INTEGRATED_GPU);
+        let discrete = candidate(1, "Discrete", vk::PhysicalDeviceType::DISCRETE_GPU);
+        let mut old = candidate(2, "Old", vk::PhysicalDeviceType::DISCRETE_GPU);
+        old.api_version = vk::API_VERSION_1_1;
+        old.extensions.clear();
+        old.present_support = vec![false];
+
+        let candidates = [integrated, discrete, old];
+
+        assert_eq!(selector().select(&candidates).unwrap().index, 1);
+
+        let by_name = selector()
+            .device_override(Some(DeviceOverride::Name("integ".to_owned())))
+            .select(&candidates)
+            .unwrap();
+        assert_eq!(by_name.index, 0);
+
+        let error = selector()
+            .device_override(Some(DeviceOverride::Index(2)))
+            .select(&candidates)
+            .unwrap_err();
+        let DeviceSelectionError::NoSuitableDevice(rejected) = &error else {
+            panic!("unexpected error {error}");
+        };
+        assert_eq!(
+            rejected[0].1,
+            vec![
+                Rejection::ApiVersion {
+                    required: vk::API_VERSION_1_3,
+                    found: vk::API_VERSION_1_1,
+                },
+                Rejection::MissingExtensions(vec!["VK_KHR_swapchain".to_owned()]),
+                Rejection::NoPresentQueue,
+            ]
+        );
+
+        assert!(matches!(
+            selector()
+                .device_override(Some(DeviceOverride::Index(7)))
+                .select(&candidates),
+            Err(DeviceSelectionError::OverrideNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+use super::command::EncoderError;
+use super::device_selector::DeviceSelectionError;
+use super::instance::InstanceBuildError;
+use super::pipeline::PipelineBuildError;
+use super::shader::ShaderError;
+use super::sync::GPUTaskError;
+use ash::vk;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+pub type GraphicsResult<T> = Result<T, GraphicsError>;
+
+#[derive(Debug)]
+pub enum GraphicsError {
+    Instance(InstanceBuildError),
+    DebugMessenger(vk::Result),
+    Surface(vk::Result),
+    DeviceSelection(DeviceSelectionError),
+    Device(vk::Result),
+    Swapchain(vk::Result),
+    Allocation(vk::Result),
+    Shader(ShaderError),
+    Pipeline(PipelineBuildError),
+    Commands(EncoderError),
+    Submit(GPUTaskError),
+}
+
+impl GraphicsError {
+    /// Vulkan result the error originates from
+    pub fn result(&self) -> Option<vk::Result> {
+        match self {
+            GraphicsError::Instance(InstanceBuildError::InstanceCreate(err))
+            | GraphicsError::DebugMessenger(err)
+            | GraphicsError::Surface(err)
+            | GraphicsError::Device(err)
+            | GraphicsError::Swapchain(err)
+            | GraphicsError::Allocation(err)
+            | GraphicsError::Shader(ShaderError::ModuleCreate(err))
+            | GraphicsError::Pipeline(
+                PipelineBuildError::LayoutCreate(err) | PipelineBuildError::PipelineCreate(err),
+            )
+            | GraphicsError::Commands(EncoderError::Vk(err)) => Some(*err),
+            GraphicsError::Submit(err) => err.result(),
+            _ => None,
+        }
+    }
+
+    /// The device has to be recreated, every object created from it is unusable
+    pub fn is_device_lost(&self) -> bool {
+        self.result() == Some(vk::Result::ERROR_DEVICE_LOST)
+    }
+}
+
+impl Display for GraphicsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphicsError::Instance(err) => write!(f, "can't create instance: {err}"),
+            GraphicsError::DebugMessenger(err) => {
+                write!(f, "can't create debug messenger: {err}")
+            }
+            GraphicsError::Surface(err) => write!(f, "surface error: {err}"),
+            GraphicsError::DeviceSelection(err) => write!(f, "can't select device: {err}"),
+            GraphicsError::Device(err) => write!(f, "device error: {err}"),
+            GraphicsError::Swapchain(err) => write!(f, "can't create swapchain: {err}"),
+            GraphicsError::Allocation(err) => write!(f, "can't allocate resource: {err}"),
+            GraphicsError::Shader(err) => write!(f, "can't load shader: {err}"),
+            GraphicsError::Pipeline(err) => write!(f, "can't create pipeline: {err}"),
+            GraphicsError::Commands(err) => write!(f, "can't record commands: {err}"),
+            GraphicsError::Submit(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for GraphicsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GraphicsError::Instance(err) => Some(err),
+            GraphicsError::DebugMessenger(err)
+            | GraphicsError::Surface(err)
+            | GraphicsError::Device(err)
+            | GraphicsError::Swapchain(err)
+            | GraphicsError::Allocation(err) => Some(err),
+            GraphicsError::DeviceSelection(err) => Some(err),
+            GraphicsError::Shader(err) => Some(err),
+            GraphicsError::Pipeline(err) => Some(err),
+            GraphicsError::Commands(err) => Some(err),
+            GraphicsError::Submit(err) => Some(err),
+        }
+    }
+}
+
+impl From<InstanceBuildError> for GraphicsError {
+    fn from(value: InstanceBuildError) -> Self {
+        GraphicsError::Instance(value)
+    }
+}
+
+impl From<DeviceSelectionError> for GraphicsError {
+    fn from(value: DeviceSelectionError) -> Self {
+        GraphicsError::DeviceSelection(value)
+    }
+}
+
+impl From<ShaderError> for GraphicsError {
+    fn from(value: ShaderError) -> Self {
+        GraphicsError::Shader(value)
+    }
+}
+
+impl From<PipelineBuildError> for GraphicsError {
+    fn from(value: PipelineBuildError) -> Self {
+        GraphicsError::Pipeline(value)
+    }
+}
+
+impl From<EncoderError> for GraphicsError {
+    fn from(value: EncoderError) -> Self {
+        GraphicsError::Commands(value)
+    }
+}
+
+impl From<GPUTaskError> for GraphicsError {
+    fn from(value: GPUTaskError) -> Self {
+        GraphicsError::Submit(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_keeps_result() {
+        let lost = GraphicsError::Submit(GPUTaskError::Wait(vk::Result::ERROR_DEVICE_LOST));
+        assert!(lost.is_device_lost());
+        assert_eq!(lost.result(), Some(vk::Result::ERROR_DEVICE_LOST));
+
+        let source = lost.source().and_then(|source| source.source()).unwrap();
+        assert_eq!(
+            source.to_string(),
+            vk::Result::ERROR_DEVICE_LOST.to_string()
+        );
+
+        let swapchain = GraphicsError::Swapchain(vk::Result::ERROR_SURFACE_LOST_KHR);
+        assert!(!swapchain.is_device_lost());
+        assert!(swapchain.source().is_some());
+
+        let shader = GraphicsError::from(ShaderError::UnknownStage {
+            path: "triangle.glsl".into(),
+        });
+        assert_eq!(shader.result(), None);
+    }
+}
@@ -0,0 +1,279 @@
+use super::command::{CommandEncoder, CommandPool, EncoderError, ThreadCommandPools};
+use super::descriptor::allocator::DescriptorAllocator;
+use super::device::Device;
+use super::render_graph::TransientPool;
+use super::sync::{semaphore::Semaphore, timeline::TimelinePoint};
+use crate::{gfx_debug_exec, gfx_debug_log};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 3;
+
+/// Resources kept alive until the GPU has finished the frame that used them
+#[derive(Default)]
+pub struct DeletionQueue {
+    resources: Vec<Box<dyn Any + Send>>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<T: Send + 'static>(&mut self, resource: T) {
+        self.resources.push(Box::new(resource));
+    }
+
+    pub fn len(&self) -> usize {
+        self.resources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// Drops the resources in the order they were pushed
+    pub fn flush(&mut self) {
+        self.resources.drain(..).for_each(drop);
+    }
+}
+
+impl Debug for DeletionQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeletionQueue")
+            .field("len", &self.resources.len())
+            .finish()
+    }
+}
+
+/// Everything a frame slot owns. It is reused once the GPU has reached `completion`.
+#[derive(Debug)]
+pub struct FrameContext {
+    acquire_semaphore: Semaphore,
+    command_buffer: vk::CommandBuffer,
+    command_pool: CommandPool,
+    secondary_pools: ThreadCommandPools,
+    descriptor_allocator: DescriptorAllocator,
+    transient_pool: TransientPool,
+    deletion_queue: DeletionQueue,
+    /// Timeline values signaled by the submissions of the last use of the slot
+    completion: Vec<TimelinePoint>,
+    /// Device frame number of the last use of the slot
+    frame_number: Option<u64>,
+    device: Arc<Device>,
+}
+
+impl FrameContext {
+    fn new(device: Arc<Device>, queue_family_index: u32, index: u32) -> VkResult<Self> {
+        let mut command_pool = CommandPool::new(
+            device.clone(),
+            queue_family_index,
+            vk::CommandPoolCreateFlags::TRANSIENT,
+        )?;
+        let command_buffer = command_pool.allocate(vk::CommandBufferLevel::PRIMARY, 1)?[0];
+        let acquire_semaphore = Semaphore::new(device.clone())?;
+
+        gfx_debug_exec!({
+            command_pool.set_object_name(&format!("frame {index} commands"));
+            acquire_semaphore.set_object_name(&format!("frame {index} acquire"));
+        });
+
+        Ok(Self {
+            acquire_semaphore,
+            command_buffer,
+            command_pool,
+            secondary_pools: ThreadCommandPools::new(device.clone(), queue_family_index),
+            descriptor_allocator: DescriptorAllocator::new(device.clone()),
+            transient_pool: TransientPool::new(),
+            deletion_queue: DeletionQueue::new(),
+            completion: vec![],
+            frame_number: None,
+            device,
+        })
+    }
+
+    /// Binary semaphore the swapchain image of the frame is acquired with
+    pub fn acquire_semaphore(&self) -> &Semaphore {
+        &self.acquire_semaphore
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// Pools worker threads record secondary command buffers of the frame from
+    pub fn secondary_pools(&self) -> &ThreadCommandPools {
+        &self.secondary_pools
+    }
+
+    pub fn descriptor_allocator(&mut self) -> &mut DescriptorAllocator {
+        &mut self.descriptor_allocator
+    }
+
+    pub fn transient_pool(&mut self) -> &mut TransientPool {
+        &mut self.transient_pool
+    }
+
+    pub fn deletion_queue(&mut self) -> &mut DeletionQueue {
+        &mut self.deletion_queue
+    }
+
+    /// Starts recording the command buffer of the frame
+    pub fn begin_commands(&self) -> Result<CommandEncoder, EncoderError> {
+        CommandEncoder::begin(
+            self.device.clone(),
+            self.command_buffer,
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        )
+    }
+
+    fn wait(&self) -> VkResult<()> {
+        self.completion
+            .iter()
+            .try_for_each(|point| point.wait(u64::MAX))
+    }
+
+    /// Waits for the last use of the slot and releases what it held. Frames retire in
+    /// submission order, so objects dropped during earlier frames are destroyed as well.
+    fn reset(&mut self) -> VkResult<()> {
+        self.wait()?;
+        self.completion.clear();
+
+        if let Some(frame_number) = self.frame_number {
+            self.device.retire_frames(frame_number);
+        }
+
+        self.deletion_queue.flush();
+        self.descriptor_allocator.reset()?;
+        self.secondary_pools.reset()?;
+        self.command_pool.reset()
+    }
+}
+
+/// Handle of the frame started by [`FrameRing::begin_frame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHandle {
+    index: u32,
+}
+
+impl FrameHandle {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// Ring of [`FrameContext`]s, the CPU records at most `frames_in_flight` frames ahead of the GPU
+#[derive(Debug)]
+pub struct FrameRing {
+    frames: Vec<FrameContext>,
+    current: u32,
+}
+
+impl FrameRing {
+    pub fn new(
+        device: Arc<Device>,
+        queue_family_index: u32,
+        frames_in_flight: u32,
+    ) -> VkResult<Self> {
+        assert!(
+            frames_in_flight > 0,
+            "At least one frame has to be in flight"
+        );
+
+        let frames = (0..frames_in_flight)
+            .map(|index| FrameContext::new(device.clone(), queue_family_index, index))
+            .collect::<VkResult<_>>()?;
+
+        Ok(Self { frames, current: 0 })
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    pub fn current_index(&self) -> u32 {
+        self.current
+    }
+
+    /// Waits until the GPU is done with the next frame slot and resets it.
+    /// Beginning again without [`FrameRing::end_frame`] reuses the same slot.
+    pub fn begin_frame(&mut self) -> VkResult<FrameHandle> {
+        self.frames[self.current as usize].reset()?;
+
+        Ok(FrameHandle {
+            index: self.current,
+        })
+    }
+
+    /// Records what the frame has to wait for before its slot is reused and moves to the next slot
+    pub fn end_frame(&mut self, frame: FrameHandle, completion: Vec<TimelinePoint>) {
+        assert_eq!(frame.index, self.current, "Frame ended out of order");
+
+        let context = &mut self.frames[frame.index as usize];
+        context.completion = completion;
+        context.frame_number = Some(context.device.end_frame());
+
+        self.current = (self.current + 1) % self.frames_in_flight();
+    }
+
+    pub fn frame(&self, frame: FrameHandle) -> &FrameContext {
+        &self.frames[frame.index as usize]
+    }
+
+    pub fn frame_mut(&mut self, frame: FrameHandle) -> &mut FrameContext {
+        &mut self.frames[frame.index as usize]
+    }
+
+    /// Waits until the GPU has finished every frame
+    pub fn wait_idle(&self) -> VkResult<()> {
+        self.frames.iter().try_for_each(FrameContext::wait)?;
+
+        if let Some(context) = self
+            .frames
+            .iter()
+            .max_by_key(|context| context.frame_number)
+        {
+            if let Some(frame_number) = context.frame_number {
+                context.device.retire_frames(frame_number);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FrameRing {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(FrameRing::drop()));
+        // Command pools and deferred resources may still be in use. A lost device
+        // can't be waited on, its resources are released anyway.
+        if let Err(e) = self.wait_idle() {
+            log::error!(target: "rust_engine::graphics", "Can't wait for frames in flight: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deletion_queue() {
+        let resource = Arc::new(());
+
+        let mut deletion_queue = DeletionQueue::new();
+        deletion_queue.push(resource.clone());
+        deletion_queue.push(resource.clone());
+
+        assert_eq!(deletion_queue.len(), 2);
+        assert_eq!(Arc::strong_count(&resource), 3);
+
+        deletion_queue.flush();
+
+        assert!(deletion_queue.is_empty());
+        assert_eq!(Arc::strong_count(&resource), 1);
+    }
+}
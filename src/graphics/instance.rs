@@ -3,21 +3,28 @@ use crate::gfx_debug_log;
 use ash::vk;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub struct Instance {
     entry: ash::Entry,
     handle: ash::Instance,
+    debug_utils: bool,
 }
 
 impl Instance {
-    fn new(entry: ash::Entry, instance: ash::Instance) -> Self {
+    fn new(entry: ash::Entry, instance: ash::Instance, debug_utils: bool) -> Self {
         Self {
             entry,
             handle: instance,
+            debug_utils,
         }
     }
 
+    /// `VK_EXT_debug_utils` is enabled, objects can be named and command buffers labeled
+    pub fn has_debug_utils(&self) -> bool {
+        self.debug_utils
+    }
+
     pub fn handle(&self) -> ash::Instance {
         self.handle.clone()
     }
@@ -27,7 +34,7 @@ impl Instance {
     }
 
     pub fn enumerate_physical_devices<'a>(
-        self: &'a Rc<Self>,
+        self: &'a Arc<Self>,
     ) -> ash::prelude::VkResult<impl ExactSizeIterator<Item = PhysicalDevice> + 'a> {
         let result = unsafe { self.handle.enumerate_physical_devices() };
 
@@ -62,6 +69,43 @@ impl Instance {
         unsafe { self.handle.get_physical_device_features(physical_device) }
     }
 
+    /// Properties with the device UUID from `VkPhysicalDeviceIDProperties`
+    pub fn get_physical_device_id_properties(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceIDProperties<'static> {
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut id_properties);
+
+        unsafe {
+            self.handle
+                .get_physical_device_properties2(physical_device, &mut properties)
+        };
+
+        id_properties.p_next = std::ptr::null_mut();
+        id_properties
+    }
+
+    pub fn enumerate_device_extension_properties(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> ash::prelude::VkResult<Vec<vk::ExtensionProperties>> {
+        unsafe {
+            self.handle
+                .enumerate_device_extension_properties(physical_device)
+        }
+    }
+
+    pub fn get_physical_device_memory_properties(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceMemoryProperties {
+        unsafe {
+            self.handle
+                .get_physical_device_memory_properties(physical_device)
+        }
+    }
+
     pub fn create_device(
         &self,
         physical_device: vk::PhysicalDevice,
@@ -148,8 +192,8 @@ impl InstanceBuilder {
         self
     }
 
-    pub fn build(self) -> Result<Rc<Instance>, InstanceBuildError> {
-        let entry = unsafe { ash::Entry::load().map_err(|e| InstanceBuildError::EntryLoad(e))? };
+    pub fn build(self) -> Result<Arc<Instance>, InstanceBuildError> {
+        let entry = unsafe { ash::Entry::load().map_err(InstanceBuildError::EntryLoad)? };
 
         let app_name = std::ffi::CString::new(self.application_name).unwrap();
 
@@ -160,6 +204,11 @@ impl InstanceBuilder {
             .engine_version(self.application_version)
             .api_version(self.api_version);
 
+        let debug_utils = self
+            .extensions
+            .iter()
+            .any(|extension| extension.as_bytes() == ash::ext::debug_utils::NAME.to_bytes());
+
         let extensions: Vec<_> = self
             .extensions
             .into_iter()
@@ -184,10 +233,10 @@ impl InstanceBuilder {
         let instance = unsafe {
             entry
                 .create_instance(&instance_info, None)
-                .map_err(|e| InstanceBuildError::InstanceCreate(e))?
+                .map_err(InstanceBuildError::InstanceCreate)?
         };
 
-        Ok(Rc::new(Instance::new(entry, instance)))
+        Ok(Arc::new(Instance::new(entry, instance, debug_utils)))
     }
 }
 
@@ -195,29 +244,29 @@ impl InstanceBuilder {
 mod tests {
     use super::*;
 
+    use crate::graphics::debug_utils::ValidationCapture;
+
     #[test]
     fn test_instance_creation() -> Result<(), InstanceBuildError> {
-        let _ = InstanceBuilder::new()
+        let instance = ValidationCapture::instance_builder()
             .application_name("hello world")
             .application_version(10)
-            .api_version(vk::API_VERSION_1_3)
-            .extensions(vec![])
-            .layers(vec![])
             .build()?;
+        let _validation = ValidationCapture::new(instance.clone());
+
+        assert!(instance.has_debug_utils());
 
         Ok(())
     }
 
     #[test]
     fn test_debug_format() {
-        let instance = InstanceBuilder::new()
+        let instance = ValidationCapture::instance_builder()
             .application_name("hello world")
             .application_version(10)
-            .api_version(vk::API_VERSION_1_3)
-            .extensions(vec![])
-            .layers(vec![])
             .build()
             .unwrap();
+        let _validation = ValidationCapture::new(instance.clone());
 
         let instance_string = format!("{:?}", instance);
 
@@ -0,0 +1,514 @@
+use ash::prelude::VkResult;
+use ash::vk;
+use std::ptr::NonNull;
+
+const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+const SMALL_HEAP_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Sub-allocating memory allocator. Memory is taken from the driver in large blocks,
+/// pooled per memory type and per resource kind (linear or optimal tiling) so that
+/// `bufferImageGranularity` never has to be taken into account inside a block.
+#[derive(Debug)]
+pub struct Allocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pools: Vec<MemoryPool>,
+}
+
+impl Allocator {
+    pub fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
+        let pools = (0..memory_properties.memory_type_count)
+            .flat_map(|memory_type_index| {
+                let heap_index =
+                    memory_properties.memory_types[memory_type_index as usize].heap_index;
+                let heap_size = memory_properties.memory_heaps[heap_index as usize].size;
+
+                let block_size = if heap_size <= SMALL_HEAP_SIZE {
+                    heap_size / 8
+                } else {
+                    DEFAULT_BLOCK_SIZE
+                };
+
+                [false, true].map(|linear| {
+                    MemoryPool::new(memory_type_index, heap_index, block_size, linear)
+                })
+            })
+            .collect();
+
+        Self {
+            memory_properties,
+            pools,
+        }
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+        linear: bool,
+    ) -> VkResult<SubAllocation> {
+        let memory_type_index = find_memory_type_index(
+            &self.memory_properties,
+            requirements.memory_type_bits,
+            required,
+            preferred,
+        )
+        .ok_or(vk::Result::ERROR_FEATURE_NOT_PRESENT)?;
+
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+
+        let pool_index = memory_type_index as usize * 2 + linear as usize;
+
+        self.pools[pool_index].allocate(
+            device,
+            requirements.size,
+            requirements.alignment.max(1),
+            host_visible,
+        )
+    }
+
+    pub fn free(&mut self, device: &ash::Device, allocation: &SubAllocation) {
+        let pool_index = allocation.memory_type_index as usize * 2 + allocation.linear as usize;
+
+        self.pools[pool_index].free(device, allocation);
+    }
+
+    pub fn statistics(&self) -> Vec<HeapStatistics> {
+        let mut statistics: Vec<_> = self.memory_properties.memory_heaps
+            [..self.memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(heap_index, heap)| HeapStatistics {
+                heap_index: heap_index as u32,
+                heap_size: heap.size,
+                flags: heap.flags,
+                ..Default::default()
+            })
+            .collect();
+
+        for pool in &self.pools {
+            let heap_statistics = &mut statistics[pool.heap_index as usize];
+
+            for block in pool.blocks.iter().flatten() {
+                heap_statistics.block_count += 1;
+                heap_statistics.block_bytes += block.size;
+                heap_statistics.allocation_count += block.free_list.allocation_count;
+                heap_statistics.allocation_bytes += block.free_list.used;
+            }
+        }
+
+        statistics
+    }
+
+    /// Releases every block. All allocations must be freed before this call.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for pool in &mut self.pools {
+            for block in pool.blocks.drain(..).flatten() {
+                block.destroy(device);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubAllocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    pub size: u64,
+    pub mapped_ptr: Option<NonNull<u8>>,
+    memory_type_index: u32,
+    linear: bool,
+    block_index: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStatistics {
+    pub heap_index: u32,
+    pub heap_size: u64,
+    pub flags: vk::MemoryHeapFlags,
+    pub block_count: u32,
+    pub block_bytes: u64,
+    pub allocation_count: u32,
+    pub allocation_bytes: u64,
+}
+
+#[derive(Debug)]
+struct MemoryPool {
+    memory_type_index: u32,
+    heap_index: u32,
+    block_size: u64,
+    linear: bool,
+    blocks: Vec<Option<MemoryBlock>>,
+}
+
+impl MemoryPool {
+    fn new(memory_type_index: u32, heap_index: u32, block_size: u64, linear: bool) -> Self {
+        Self {
+            memory_type_index,
+            heap_index,
+            block_size,
+            linear,
+            blocks: Vec::new(),
+        }
+    }
+
+    fn allocate(
+        &mut self,
+        device: &ash::Device,
+        size: u64,
+        alignment: u64,
+        host_visible: bool,
+    ) -> VkResult<SubAllocation> {
+        let dedicated = size > self.block_size / 2;
+
+        if !dedicated {
+            let found = self
+                .blocks
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, block)| block.as_mut().map(|block| (index, block)))
+                .filter(|(_, block)| !block.dedicated)
+                .find_map(|(index, block)| {
+                    block
+                        .free_list
+                        .allocate(size, alignment)
+                        .map(|offset| (index, offset))
+                });
+
+            if let Some((block_index, offset)) = found {
+                return Ok(self.sub_allocation(block_index, offset, size));
+            }
+        }
+
+        let block_size = if dedicated { size } else { self.block_size };
+        let mut block = MemoryBlock::new(
+            device,
+            self.memory_type_index,
+            block_size,
+            host_visible,
+            dedicated,
+        )?;
+
+        let offset = block
+            .free_list
+            .allocate(size, alignment)
+            .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+
+        let block_index = match self.blocks.iter().position(Option::is_none) {
+            Some(index) => {
+                self.blocks[index] = Some(block);
+                index
+            }
+            None => {
+                self.blocks.push(Some(block));
+                self.blocks.len() - 1
+            }
+        };
+
+        Ok(self.sub_allocation(block_index, offset, size))
+    }
+
+    fn sub_allocation(&self, block_index: usize, offset: u64, size: u64) -> SubAllocation {
+        let block = self.blocks[block_index].as_ref().unwrap();
+
+        SubAllocation {
+            memory: block.memory,
+            offset,
+            size,
+            mapped_ptr: block
+                .mapped_ptr
+                .map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr().add(offset as usize)) }),
+            memory_type_index: self.memory_type_index,
+            linear: self.linear,
+            block_index,
+        }
+    }
+
+    fn free(&mut self, device: &ash::Device, allocation: &SubAllocation) {
+        let Some(block) = self.blocks[allocation.block_index].as_mut() else {
+            return;
+        };
+
+        block.free_list.free(allocation.offset, allocation.size);
+
+        if !block.free_list.is_empty() {
+            return;
+        }
+
+        let dedicated = block.dedicated;
+
+        // Keep a single empty block around so that short-lived allocations
+        // do not hit the driver every time
+        let has_other_empty = self.blocks.iter().enumerate().any(|(index, other)| {
+            index != allocation.block_index
+                && other
+                    .as_ref()
+                    .is_some_and(|other| !other.dedicated && other.free_list.is_empty())
+        });
+
+        if dedicated || has_other_empty {
+            if let Some(block) = self.blocks[allocation.block_index].take() {
+                block.destroy(device);
+            }
+        }
+    }
+}
+
+// Mapped pointers are only dereferenced through the resource owning the range
+unsafe impl Send for SubAllocation {}
+unsafe impl Sync for SubAllocation {}
+
+#[derive(Debug)]
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: u64,
+    mapped_ptr: Option<NonNull<u8>>,
+    dedicated: bool,
+    free_list: FreeList,
+}
+
+unsafe impl Send for MemoryBlock {}
+
+impl MemoryBlock {
+    fn new(
+        device: &ash::Device,
+        memory_type_index: u32,
+        size: u64,
+        host_visible: bool,
+        dedicated: bool,
+    ) -> VkResult<Self> {
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+
+        let memory = unsafe { device.allocate_memory(&allocate_info, None)? };
+
+        let mapped_ptr = if host_visible {
+            let ptr = unsafe {
+                device
+                    .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                    .inspect_err(|_| device.free_memory(memory, None))?
+            };
+
+            NonNull::new(ptr as *mut u8)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            memory,
+            size,
+            mapped_ptr,
+            dedicated,
+            free_list: FreeList::new(size),
+        })
+    }
+
+    fn destroy(self, device: &ash::Device) {
+        unsafe {
+            if self.mapped_ptr.is_some() {
+                device.unmap_memory(self.memory);
+            }
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// First-fit free list over a block of `size` bytes. Free ranges are kept sorted
+/// by offset and merged with their neighbours on free.
+#[derive(Debug)]
+pub struct FreeList {
+    size: u64,
+    used: u64,
+    allocation_count: u32,
+    free_ranges: Vec<(u64, u64)>,
+}
+
+impl FreeList {
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            used: 0,
+            allocation_count: 0,
+            free_ranges: vec![(0, size)],
+        }
+    }
+
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let (index, offset) = self.free_ranges.iter().enumerate().find_map(
+            |(index, &(range_offset, range_size))| {
+                let offset = range_offset.next_multiple_of(alignment);
+                let padding = offset - range_offset;
+
+                (range_size >= padding + size).then_some((index, offset))
+            },
+        )?;
+
+        let (range_offset, range_size) = self.free_ranges.remove(index);
+        let range_end = range_offset + range_size;
+        let allocation_end = offset + size;
+
+        if allocation_end < range_end {
+            self.free_ranges
+                .insert(index, (allocation_end, range_end - allocation_end));
+        }
+        if range_offset < offset {
+            self.free_ranges
+                .insert(index, (range_offset, offset - range_offset));
+        }
+
+        self.used += size;
+        self.allocation_count += 1;
+
+        Some(offset)
+    }
+
+    pub fn free(&mut self, offset: u64, size: u64) {
+        let index = self
+            .free_ranges
+            .partition_point(|&(range_offset, _)| range_offset < offset);
+
+        self.free_ranges.insert(index, (offset, size));
+
+        if index + 1 < self.free_ranges.len() {
+            let (next_offset, next_size) = self.free_ranges[index + 1];
+            if offset + size == next_offset {
+                self.free_ranges[index].1 += next_size;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+
+        if index > 0 {
+            let (prev_offset, prev_size) = self.free_ranges[index - 1];
+            if prev_offset + prev_size == offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+
+        self.used -= size;
+        self.allocation_count -= 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocation_count == 0
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Picks a memory type allowed by `type_bits` that has all `required` flags,
+/// preferring one that also has the `preferred` flags
+pub fn find_memory_type_index(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    required: vk::MemoryPropertyFlags,
+    preferred: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    let memory_types =
+        &memory_properties.memory_types[..memory_properties.memory_type_count as usize];
+
+    let find = |flags: vk::MemoryPropertyFlags| {
+        memory_types
+            .iter()
+            .enumerate()
+            .find(|(index, memory_type)| {
+                type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+            })
+            .map(|(index, _)| index as u32)
+    };
+
+    find(required | preferred).or_else(|| find(required))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_list_alignment() {
+        let mut free_list = FreeList::new(1024);
+
+        assert_eq!(free_list.allocate(10, 1), Some(0));
+        assert_eq!(free_list.allocate(16, 256), Some(256));
+        assert_eq!(free_list.allocate(100, 1), Some(10));
+        assert_eq!(free_list.used(), 126);
+    }
+
+    #[test]
+    fn test_free_list_coalesce() {
+        let mut free_list = FreeList::new(300);
+
+        let a = free_list.allocate(100, 1).unwrap();
+        let b = free_list.allocate(100, 1).unwrap();
+        let c = free_list.allocate(100, 1).unwrap();
+
+        assert_eq!(free_list.allocate(1, 1), None);
+
+        free_list.free(a, 100);
+        free_list.free(c, 100);
+        assert_eq!(free_list.allocate(200, 1), None);
+
+        free_list.free(b, 100);
+        assert!(free_list.is_empty());
+        assert_eq!(free_list.allocate(300, 1), Some(0));
+    }
+
+    #[test]
+    fn test_find_memory_type_index() {
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 3,
+            memory_heap_count: 2,
+            ..Default::default()
+        };
+        memory_properties.memory_types[0].property_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        memory_properties.memory_types[1].property_flags =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        memory_properties.memory_types[2].property_flags = vk::MemoryPropertyFlags::HOST_VISIBLE
+            | vk::MemoryPropertyFlags::HOST_COHERENT
+            | vk::MemoryPropertyFlags::HOST_CACHED;
+
+        let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+        assert_eq!(
+            find_memory_type_index(
+                &memory_properties,
+                0b111,
+                host,
+                vk::MemoryPropertyFlags::HOST_CACHED
+            ),
+            Some(2)
+        );
+        assert_eq!(
+            find_memory_type_index(
+                &memory_properties,
+                0b011,
+                host,
+                vk::MemoryPropertyFlags::HOST_CACHED
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            find_memory_type_index(
+                &memory_properties,
+                0b110,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vk::MemoryPropertyFlags::empty()
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use allocator::{HeapStatistics, SubAllocation};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::fmt::{Debug, Formatter};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+pub mod allocator;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationDescription {
+    pub requirements: vk::MemoryRequirements,
+    pub required_properties: vk::MemoryPropertyFlags,
+    pub preferred_properties: vk::MemoryPropertyFlags,
+    pub linear: bool,
+}
+
+impl AllocationDescription {
+    pub fn new(requirements: vk::MemoryRequirements) -> Self {
+        Self {
+            requirements,
+            required_properties: vk::MemoryPropertyFlags::empty(),
+            preferred_properties: vk::MemoryPropertyFlags::empty(),
+            linear: true,
+        }
+    }
+
+    pub fn required_properties(mut self, properties: vk::MemoryPropertyFlags) -> Self {
+        self.required_properties = properties;
+        self
+    }
+
+    pub fn preferred_properties(mut self, properties: vk::MemoryPropertyFlags) -> Self {
+        self.preferred_properties = properties;
+        self
+    }
+
+    pub fn linear(mut self, linear: bool) -> Self {
+        self.linear = linear;
+        self
+    }
+}
+
+/// Range of device memory owned by the [`Device`] allocator.
+/// The range is returned to its pool once the frames in flight during the drop have retired.
+pub struct Allocation {
+    inner: SubAllocation,
+    device: Arc<Device>,
+}
+
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.inner.memory
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.inner.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.inner.size
+    }
+
+    /// Pointer to the start of the allocation if it lives in host visible memory
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        self.inner.mapped_ptr
+    }
+}
+
+impl Debug for Allocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Allocation")
+            .field("memory", &self.inner.memory)
+            .field("offset", &self.inner.offset)
+            .field("size", &self.inner.size)
+            .finish()
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        let inner = self.inner.clone();
+        self.device.defer_destroy(move |device| {
+            device.allocator().free(&device.handle(), &inner);
+        });
+    }
+}
+
+pub trait DeviceMemoryFns {
+    fn allocate_memory(
+        self: &Arc<Self>,
+        description: &AllocationDescription,
+    ) -> VkResult<Allocation>;
+    fn memory_statistics(&self) -> Vec<HeapStatistics>;
+}
+
+impl DeviceCreateExtend<vk::MemoryAllocateInfo<'_>, vk::DeviceMemory> for Device {
+    fn create(&self, create_info: &vk::MemoryAllocateInfo<'_>) -> VkResult<vk::DeviceMemory> {
+        unsafe { self.handle().allocate_memory(create_info, None) }
+    }
+}
+
+impl DeviceDestroyExtend<vk::DeviceMemory> for Device {
+    fn destroy(&self, vk_struct: vk::DeviceMemory) {
+        unsafe {
+            self.handle().free_memory(vk_struct, None);
+        }
+    }
+}
+
+impl DeviceMemoryFns for Device {
+    fn allocate_memory(
+        self: &Arc<Self>,
+        description: &AllocationDescription,
+    ) -> VkResult<Allocation> {
+        let inner = self.allocator().allocate(
+            &self.handle(),
+            description.requirements,
+            description.required_properties,
+            description.preferred_properties,
+            description.linear,
+        )?;
+
+        Ok(Allocation {
+            inner,
+            device: self.clone(),
+        })
+    }
+
+    fn memory_statistics(&self) -> Vec<HeapStatistics> {
+        self.allocator().statistics()
+    }
+}
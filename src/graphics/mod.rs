@@ -1,352 +1,734 @@
 use self::{
+    buffer::{Buffer, BufferDescription},
+    command::EncoderError,
     debug_utils::{DebugUtils, DebugUtilsBuilder},
-    device::{Device, DeviceBuilder, Queue, QueueDescription, VulkanDevice},
+    device::{Device, DeviceBuilder, Queue, QueueDescription},
+    device_selector::{DeviceCandidate, DeviceOverride, DeviceSelector},
+    frame::{FrameHandle, FrameRing, DEFAULT_FRAMES_IN_FLIGHT},
     instance::{Instance, InstanceBuilder},
+    offscreen::OffscreenTarget,
+    pipeline::{
+        vertex::{DefaultVertex, MvpPushConstants},
+        Pipeline, PipelineBuilder,
+    },
+    queues::{QueueFamilies, QueueRole, Queues},
+    render_graph::{
+        BufferAccess, ImageAccess, ImageHandle, ImportedImage, LoadOp, PassContext, RenderGraph,
+    },
+    shader::ShaderModule,
     surface::Surface,
-    swapchain::{Swapchain, SwapchainDescription, SwapchainImageDescription},
+    swapchain::{Swapchain, SwapchainConfig, SwapchainDescription, SwapchainImageDescription},
     sync::{
-        fence::Fence, semaphore::Semaphore, submit_task, task_from_runner, GPUTask, SubmitInfo,
+        semaphore::Semaphore,
+        submit_task::{self, SubmitTask},
+        task_from_runner, GPUTask, GPUTaskError, SubmitInfo,
     },
 };
 use super::{APP_MAJOR_VERSION, APP_MINOR_VERSION, APP_NAME, APP_PATCH_VERSION};
-use crate::utils::gfx::enumerate_required_extensions;
+use crate::gfx_debug_exec;
+use crate::utils::gfx::{enumerate_headless_extensions, enumerate_required_extensions};
 use crate::utils::{make_version, IntoExtent2D};
 use ash::vk;
-use std::rc::Rc;
+#[cfg(feature = "shader_hot_reload")]
+use shader::watcher::ShaderWatcher;
+use std::sync::Arc;
 use winit::dpi::PhysicalSize;
 
+pub use error::{GraphicsError, GraphicsResult};
+
+mod buffer;
+mod command;
 mod debug_utils;
+mod descriptor;
 mod device;
+mod device_selector;
+mod error;
+mod frame;
 mod instance;
+mod memory;
+mod offscreen;
+mod pipeline;
+mod queues;
+mod render_graph;
+mod shader;
 mod surface;
 mod swapchain;
 mod sync;
 mod texture;
 
-const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.2, 1.0, 1.0];
+const DEFAULT_SHADERS: [&str; 2] = ["default.vert.glsl", "default.frag.glsl"];
+const TRIANGLE: [DefaultVertex; 3] = [
+    DefaultVertex {
+        position: [0.0, -0.5, 0.0],
+        color: [1.0, 0.0, 0.0],
+    },
+    DefaultVertex {
+        position: [0.5, 0.5, 0.0],
+        color: [0.0, 1.0, 0.0],
+    },
+    DefaultVertex {
+        position: [-0.5, 0.5, 0.0],
+        color: [0.0, 0.0, 1.0],
+    },
+];
+
+#[derive(Debug)]
+enum RenderTarget {
+    Surface {
+        /// Declared before `surface`, a surface has to outlive its swapchain
+        swapchain: Option<Swapchain>,
+        surface: Arc<Surface>,
+        /// Size of the window, the swapchain extent may differ if the surface dictates one
+        extent: vk::Extent2D,
+        /// Set on resize and when the swapchain reports `OUT_OF_DATE` or `SUBOPTIMAL`.
+        /// The swapchain is rebuilt before the next frame.
+        out_of_date: bool,
+        /// Signaled when rendering into the swapchain image with the same index has finished
+        render_semaphores: Vec<Semaphore>,
+    },
+    Offscreen(OffscreenTarget),
+}
+
+impl RenderTarget {
+    fn color_format(&self) -> Option<vk::Format> {
+        match self {
+            RenderTarget::Surface { swapchain, .. } => swapchain.as_ref().map(|t| t.image_format()),
+            RenderTarget::Offscreen(_) => Some(OffscreenTarget::FORMAT),
+        }
+    }
+
+    fn mark_out_of_date(&mut self) {
+        if let RenderTarget::Surface { out_of_date, .. } = self {
+            *out_of_date = true;
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct GraphicsState {
-    swapchain: Option<Swapchain>,
-    queue: Queue,
-    device: Rc<Device>,
-    surface: Rc<Surface>,
+    target: RenderTarget,
+    swapchain_config: SwapchainConfig,
+    pipeline: Option<Pipeline>,
+    pipeline_color_format: vk::Format,
+    vertex_buffer: Buffer,
+    #[cfg(feature = "shader_hot_reload")]
+    shader_watcher: ShaderWatcher,
+    queues: Queues,
+    device: Arc<Device>,
     _debug_utils: Option<DebugUtils>,
-    _instance: Rc<Instance>,
-
-    present_semaphores: Vec<Semaphore>,
-    render_semaphores: Vec<Semaphore>,
-    fences: Vec<Fence>,
-    _command_pools: Vec<vk::CommandPool>,
-    command_buffers: Vec<vk::CommandBuffer>,
-    current_frame: u32,
+    _instance: Arc<Instance>,
+    frames: FrameRing,
 }
 
 impl GraphicsState {
-    pub fn new(window: &winit::window::Window) -> Self {
-        let instance = create_instance(window);
+    pub fn new(window: &winit::window::Window) -> GraphicsResult<Self> {
+        let instance = create_instance(
+            enumerate_required_extensions(window).map_err(GraphicsError::Surface)?,
+        )?;
+
+        let surface = Arc::new(
+            Surface::from_window(instance.clone(), window).map_err(GraphicsError::Surface)?,
+        );
+
+        Self::with_surface(instance, Some(surface), window.inner_size().into_extent())
+    }
+
+    /// Creates state that presents to a `VK_EXT_headless_surface` swapchain,
+    /// so the full acquire/present loop runs without a window system.
+    pub fn new_headless(extent: vk::Extent2D) -> GraphicsResult<Self> {
+        let instance = create_instance(enumerate_headless_extensions())?;
+
+        let surface =
+            Arc::new(Surface::headless(instance.clone()).map_err(GraphicsError::Surface)?);
 
+        Self::with_surface(instance, Some(surface), extent)
+    }
+
+    /// Creates state that renders into an [`OffscreenTarget`] instead of a window.
+    /// Rendered frames can be read back with [`GraphicsState::read_pixels`].
+    pub fn new_offscreen(extent: vk::Extent2D) -> GraphicsResult<Self> {
+        let instance = create_instance(vec![])?;
+
+        Self::with_surface(instance, None, extent)
+    }
+
+    fn with_surface(
+        instance: Arc<Instance>,
+        surface: Option<Arc<Surface>>,
+        extent: vk::Extent2D,
+    ) -> GraphicsResult<Self> {
         let _debug_utils = if cfg!(feature = "gfx_debug_msg") {
             Some(
                 DebugUtilsBuilder::new()
                     .build(instance.clone())
-                    .expect("Error while create DebugUtilsMessenger"),
+                    .map_err(GraphicsError::DebugMessenger)?,
             )
         } else {
             None
         };
 
-        let surface = Rc::new(
-            Surface::from_window(instance.clone(), window).expect("Error while create surface"),
+        let physical_devices: Vec<_> = instance
+            .enumerate_physical_devices()
+            .map_err(GraphicsError::Device)?
+            .collect();
+
+        let candidates = physical_devices
+            .iter()
+            .enumerate()
+            .map(|(index, pd)| DeviceCandidate::query(index, pd, surface.as_deref()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(GraphicsError::Device)?;
+
+        let selection = DeviceSelector::new()
+            .api_version(vk::API_VERSION_1_3)
+            .extensions(Device::default_extensions())
+            .require_feature("samplerAnisotropy", |features| {
+                features.sampler_anisotropy == vk::TRUE
+            })
+            .device_override(DeviceOverride::from_env())
+            .select(&candidates)?;
+
+        log::info!(
+            target: "rust_engine::graphics",
+            "Using {} (score {})",
+            candidates[selection.index].name,
+            selection.score
         );
 
-        let (physical_device, queue_family_index) = instance
-            .enumerate_physical_devices()
-            .unwrap()
+        let queue_family_index = selection.queue_family_index;
+        let queue_families = QueueFamilies::discover(
+            &candidates[selection.index].queue_families,
+            queue_family_index,
+        );
+
+        log::info!(
+            target: "rust_engine::graphics",
+            "Queue families: graphics {}, compute {}, transfer {}",
+            queue_families.graphics,
+            queue_families.compute,
+            queue_families.transfer
+        );
+
+        let physical_device =
+            physical_devices
+                .into_iter()
+                .nth(selection.index)
+                .ok_or(GraphicsError::Device(
+                    vk::Result::ERROR_INITIALIZATION_FAILED,
+                ))?;
+
+        let queue_descriptions = queue_families
+            .unique()
             .into_iter()
-            .filter_map(|pd| {
-                pd.get_queue_family_properties()
-                    .into_iter()
-                    .enumerate()
-                    .find(|(index, qfp)| {
-                        qfp.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                            && surface
-                                .get_physical_device_surface_support(&pd, *index as u32)
-                                .unwrap_or(false)
-                    })
-                    .map(|(index, _)| (pd, index as u32))
+            .map(|family| {
+                QueueDescription::new()
+                    .queue_family_index(family)
+                    .priority(vec![1.0f32])
             })
-            .min_by_key(|(pd, _)| match pd.get_properties().device_type {
-                vk::PhysicalDeviceType::DISCRETE_GPU => 0,
-                vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
-                vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
-                vk::PhysicalDeviceType::CPU => 3,
-                vk::PhysicalDeviceType::OTHER => 4,
-                _ => 5,
-            })
-            .expect("No device available");
-
-        let queue_description = QueueDescription::new()
-            .queue_family_index(queue_family_index)
-            .priority(vec![1.0f32]);
+            .collect();
 
         let device_features = vk::PhysicalDeviceFeatures::default().sampler_anisotropy(true);
 
-        let (device, mut queues) = DeviceBuilder::new()
-            .queues(vec![queue_description])
+        let (device, queues) = DeviceBuilder::new()
+            .queues(queue_descriptions)
             .features(device_features)
             .push_extend(
                 vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true),
             )
             .build(instance.clone(), physical_device)
-            .expect("Error while create device");
+            .map_err(GraphicsError::Device)?;
 
-        let queue = queues.next().unwrap();
+        let queues = Queues::new(queue_families, queues).map_err(GraphicsError::Device)?;
 
-        let (present_semaphores, render_semaphores) = {
-            let present_semaphores = (0..MAX_FRAMES_IN_FLIGHT)
-                .map(|_| Semaphore::new(device.clone()).unwrap())
-                .collect();
+        let frames = FrameRing::new(device.clone(), queue_family_index, DEFAULT_FRAMES_IN_FLIGHT)
+            .map_err(GraphicsError::Device)?;
 
-            let render_semaphores = (0..MAX_FRAMES_IN_FLIGHT)
-                .map(|_| Semaphore::new(device.clone()).unwrap())
-                .collect();
+        let vertex_buffer = Buffer::new(
+            device.clone(),
+            &BufferDescription::vertex::<DefaultVertex>(TRIANGLE.len()),
+        )
+        .map_err(GraphicsError::Allocation)?;
+        gfx_debug_exec!(vertex_buffer.set_object_name("triangle vertices"));
+
+        vertex_buffer
+            .upload(queues.graphics(), &TRIANGLE)
+            .map_err(GraphicsError::Allocation)?;
+
+        let target = match surface {
+            Some(surface) => RenderTarget::Surface {
+                surface,
+                swapchain: None,
+                extent,
+                out_of_date: true,
+                render_semaphores: vec![],
+            },
+            None => RenderTarget::Offscreen(
+                OffscreenTarget::new(device.clone(), extent).map_err(GraphicsError::Allocation)?,
+            ),
+        };
 
-            (present_semaphores, render_semaphores)
+        let mut state = Self {
+            _instance: instance,
+            _debug_utils,
+            target,
+            swapchain_config: SwapchainConfig::default(),
+            pipeline: None,
+            pipeline_color_format: vk::Format::UNDEFINED,
+            vertex_buffer,
+            #[cfg(feature = "shader_hot_reload")]
+            shader_watcher: ShaderWatcher::new(shader::shader_dir()),
+            device,
+            queues,
+            frames,
         };
 
-        let fences = (0..MAX_FRAMES_IN_FLIGHT)
-            .map(|_| Fence::new(device.clone(), false).unwrap())
-            .collect();
+        state.update_pipeline()?;
+
+        Ok(state)
+    }
 
-        let command_pool_info = vk::CommandPoolCreateInfo::default()
-            .queue_family_index(queue_family_index)
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
-
-        let (command_pools, command_buffers) = unsafe {
-            let mut command_pools = vec![];
-            let mut command_buffers = vec![];
-            for _ in 0..MAX_FRAMES_IN_FLIGHT {
-                let command_pool = device
-                    .handle()
-                    .create_command_pool(&command_pool_info, None)
-                    .expect("Error while create command pool");
-
-                let command_buffer = {
-                    let command_buffer_info = vk::CommandBufferAllocateInfo::default()
-                        .command_pool(command_pool)
-                        .command_buffer_count(1)
-                        .level(vk::CommandBufferLevel::PRIMARY);
-
-                    device
-                        .handle()
-                        .allocate_command_buffers(&command_buffer_info)
-                        .expect("Error while allocate command buffer")
-                        .get(0)
-                        .unwrap()
-                        .to_owned()
-                };
-
-                command_pools.push(command_pool);
-                command_buffers.push(command_buffer);
+    pub(crate) fn resize(&mut self, new_size: PhysicalSize<u32>) -> GraphicsResult<()> {
+        self.resize_extent(new_size.into_extent())
+    }
+
+    /// Swapchains are rebuilt lazily before the next frame, offscreen targets right away.
+    /// Offscreen targets keep their size when `new_extent` is empty.
+    pub fn resize_extent(&mut self, new_extent: vk::Extent2D) -> GraphicsResult<()> {
+        match &mut self.target {
+            RenderTarget::Surface {
+                extent,
+                out_of_date,
+                ..
+            } => {
+                *extent = new_extent;
+                *out_of_date = true;
+
+                Ok(())
             }
+            RenderTarget::Offscreen(_) if new_extent.width == 0 || new_extent.height == 0 => Ok(()),
+            RenderTarget::Offscreen(target) => {
+                // The old target is destroyed once the frames using it have retired
+                *target = OffscreenTarget::new(self.device.clone(), new_extent)
+                    .map_err(GraphicsError::Allocation)?;
 
-            (command_pools, command_buffers)
-        };
+                self.update_pipeline()
+            }
+        }
+    }
 
-        Self {
-            _instance: instance,
-            _debug_utils,
-            surface,
-            device,
-            queue,
-            swapchain: None,
-            present_semaphores,
-            render_semaphores,
-            fences,
-            _command_pools: command_pools,
-            command_buffers,
-            current_frame: 0,
+    /// Applies `config` when the swapchain is rebuilt before the next frame
+    pub fn set_swapchain_config(&mut self, config: SwapchainConfig) {
+        self.swapchain_config = config;
+        self.target.mark_out_of_date();
+    }
+
+    pub fn swapchain(&self) -> Option<&Swapchain> {
+        match &self.target {
+            RenderTarget::Surface { swapchain, .. } => swapchain.as_ref(),
+            RenderTarget::Offscreen(_) => None,
         }
     }
 
-    pub(crate) fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        let swapchain = create_swapchain(
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frames.frames_in_flight()
+    }
+
+    /// Waits for the frames in flight and recreates the ring with `frames_in_flight` slots
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: u32) -> GraphicsResult<()> {
+        self.frames.wait_idle().map_err(GPUTaskError::Wait)?;
+
+        self.frames = FrameRing::new(
             self.device.clone(),
-            &self.surface,
-            new_size.into_extent(),
-            self.swapchain.as_ref().map(|t| t.handle()),
-        );
+            self.queues.graphics().family_index(),
+            frames_in_flight,
+        )
+        .map_err(GraphicsError::Device)?;
+
+        Ok(())
+    }
 
-        self.swapchain = Some(swapchain);
+    /// Queue for `role`, shared with the graphics queue when the device has no dedicated family
+    pub fn queue(&self, role: QueueRole) -> &Queue {
+        self.queues.get(role)
     }
 
-    pub fn render(&mut self) {
-        let current_fence = &self.fences[self.current_frame as usize];
+    pub fn queues(&self) -> &Queues {
+        &self.queues
+    }
 
-        let swapchain = self.swapchain.as_ref().unwrap();
+    /// Rebuilds the swapchain for the current surface extent together with the
+    /// per-image semaphores. Returns `false` while the surface has no area,
+    /// e.g. when the window is minimized.
+    fn recreate_swapchain(&mut self) -> GraphicsResult<bool> {
+        let RenderTarget::Surface {
+            surface,
+            swapchain,
+            extent,
+            out_of_date,
+            render_semaphores,
+        } = &mut self.target
+        else {
+            return Ok(false);
+        };
 
-        let image_result = swapchain.get_current_image(
-            self.present_semaphores[self.current_frame as usize].handle(),
-            Some(current_fence.handle()),
-        );
+        let Some(new_swapchain) = create_swapchain(
+            self.device.clone(),
+            surface,
+            &self.swapchain_config,
+            *extent,
+            swapchain.as_ref().map(|t| t.handle()),
+        )?
+        else {
+            return Ok(false);
+        };
 
-        current_fence.wait(u64::MAX).unwrap();
-        current_fence.reset();
+        gfx_debug_exec!(new_swapchain.set_object_name("swapchain"));
 
-        let (current_image, image_index) = match image_result {
-            Ok((current_image, image_index, suboptimal)) => {
-                if suboptimal {
-                    return;
-                }
+        if render_semaphores.len() != new_swapchain.image_count() {
+            *render_semaphores = (0..new_swapchain.image_count())
+                .map(|_| Semaphore::new(self.device.clone()))
+                .collect::<Result<_, _>>()
+                .map_err(GraphicsError::Swapchain)?;
 
-                (current_image, image_index)
+            for (index, semaphore) in render_semaphores.iter().enumerate() {
+                gfx_debug_exec!(semaphore.set_object_name(&format!("render finished {index}")));
             }
-            Err(_) => {
-                return;
-            }
-        };
+        }
+
+        *swapchain = Some(new_swapchain);
+        *out_of_date = false;
+
+        self.update_pipeline()?;
 
-        let current_command_buffer = self.command_buffers[self.current_frame as usize];
-
-        let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-
-        unsafe {
-            self.device
-                .handle()
-                .begin_command_buffer(current_command_buffer, &command_buffer_begin_info)
-                .unwrap();
-
-            // Begin rendering
-            let image_barrier = [vk::ImageMemoryBarrier::default()
-                .image(current_image.image())
-                .src_access_mask(vk::AccessFlags::empty())
-                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })];
-
-            self.device.handle().cmd_pipeline_barrier(
-                current_command_buffer,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &image_barrier,
+        Ok(true)
+    }
+
+    /// Acquires the next swapchain image, rebuilding the swapchain when it is out of date.
+    /// Returns `None` when no image can be rendered this frame.
+    fn acquire_image(&mut self, acquire_semaphore: vk::Semaphore) -> GraphicsResult<Option<u32>> {
+        // A freshly created swapchain can be out of date already if the window keeps resizing
+        for _ in 0..2 {
+            let needs_recreate = matches!(
+                self.target,
+                RenderTarget::Surface {
+                    out_of_date: true,
+                    ..
+                }
             );
+            if needs_recreate && !self.recreate_swapchain()? {
+                return Ok(None);
+            }
 
-            let color_attachment = vk::RenderingAttachmentInfoKHR::default()
-                .image_view(current_image.image_view())
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.1, 0.2, 1.0, 1.0],
-                    },
-                });
-
-            let render_extent = vk::Extent2D {
-                width: swapchain.extent().width,
-                height: swapchain.extent().height,
+            let RenderTarget::Surface {
+                swapchain: Some(swapchain),
+                out_of_date,
+                ..
+            } = &mut self.target
+            else {
+                return Ok(None);
             };
-            let rendering_info = vk::RenderingInfoKHR::default()
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: render_extent,
-                })
-                .layer_count(1)
-                .color_attachments(std::slice::from_ref(&color_attachment));
-
-            self.device
-                .handle()
-                .cmd_begin_rendering(current_command_buffer, &rendering_info);
-
-            // End rendering
-            self.device
-                .handle()
-                .cmd_end_rendering(current_command_buffer);
-
-            let image_barrier = [vk::ImageMemoryBarrier::default()
-                .image(current_image.image())
-                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                .dst_access_mask(vk::AccessFlags::empty())
-                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })];
-
-            self.device.handle().cmd_pipeline_barrier(
-                current_command_buffer,
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &image_barrier,
-            );
 
-            // End record command buffer
-            self.device
-                .handle()
-                .end_command_buffer(current_command_buffer)
-                .unwrap();
+            match swapchain.get_current_image(acquire_semaphore, None) {
+                Ok((_, image_index, suboptimal)) => {
+                    // The image can still be presented, the swapchain is rebuilt next frame
+                    *out_of_date = suboptimal;
+                    return Ok(Some(image_index));
+                }
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => *out_of_date = true,
+                Err(e) => return Err(GPUTaskError::Acquire(e).into()),
+            }
         }
 
-        let wait_semaphore = self.present_semaphores[self.current_frame as usize].handle();
-        let signal_semaphore = self.render_semaphores[self.current_frame as usize].handle();
-        let wait_dst_stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        Ok(None)
+    }
 
-        let submit_runner = submit_task::submit(
-            self.queue.clone(),
-            SubmitInfo {
-                signal_semaphore,
-                wait_semaphore,
-                wait_dst_stage_mask,
-                command_buffers: vec![current_command_buffer],
-            },
-            Some(current_fence.as_shared()),
-        );
+    /// Rebuilds the default pipeline when the target color format changes
+    fn update_pipeline(&mut self) -> GraphicsResult<()> {
+        let Some(color_format) = self.target.color_format() else {
+            return Ok(());
+        };
+
+        if self.pipeline.is_some() && self.pipeline_color_format == color_format {
+            return Ok(());
+        }
+
+        let pipeline = create_default_pipeline(self.device.clone(), color_format)?;
+
+        self.pipeline = Some(pipeline);
+        self.pipeline_color_format = color_format;
+
+        Ok(())
+    }
+
+    /// Rebuilds pipelines whose shaders changed on disk.
+    /// On compile errors the old pipeline stays active.
+    #[cfg(feature = "shader_hot_reload")]
+    fn reload_shaders(&mut self) {
+        let changed = self.shader_watcher.poll();
+        let affected = changed.iter().any(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| DEFAULT_SHADERS.contains(&name))
+        });
+
+        if !affected || self.pipeline.is_none() {
+            return;
+        }
+
+        match create_default_pipeline(self.device.clone(), self.pipeline_color_format) {
+            Ok(pipeline) => {
+                log::info!(target: "rust_engine::graphics", "Reloaded default shaders");
+                self.pipeline = Some(pipeline);
+            }
+            Err(e) => {
+                log::error!(target: "rust_engine::graphics", "Shader reload failed: {e}");
+            }
+        }
+    }
+
+    /// Returns the RGBA bytes of the last rendered frame, or `None` if the target isn't offscreen
+    pub fn read_pixels(&self) -> GraphicsResult<Option<Vec<u8>>> {
+        match &self.target {
+            RenderTarget::Offscreen(target) => target
+                .read_pixels()
+                .map(Some)
+                .map_err(GraphicsError::Device),
+            RenderTarget::Surface { .. } => Ok(None),
+        }
+    }
+
+    /// Swapchain images that are out of date or unavailable skip the frame without an error
+    pub fn render(&mut self) -> GraphicsResult<()> {
+        #[cfg(feature = "shader_hot_reload")]
+        self.reload_shaders();
+
+        match &self.target {
+            RenderTarget::Surface { .. } => self.render_to_swapchain(),
+            RenderTarget::Offscreen(_) => self.render_offscreen(),
+        }
+    }
 
-        let submit_task = task_from_runner(submit_runner).unwrap();
+    fn render_to_swapchain(&mut self) -> GraphicsResult<()> {
+        let frame = self.frames.begin_frame().map_err(GPUTaskError::Wait)?;
+        let acquire_semaphore = self.frames.frame(frame).acquire_semaphore().handle();
 
-        submit_task.wait().unwrap();
+        let Some(image_index) = self.acquire_image(acquire_semaphore)? else {
+            return Ok(());
+        };
 
+        let submit_task = match self.submit_swapchain_frame(frame, image_index, acquire_semaphore) {
+            Ok(submit_task) => submit_task,
+            Err(e) => {
+                self.release_acquired_image(frame, acquire_semaphore);
+                return Err(e);
+            }
+        };
+        self.frames.end_frame(frame, submit_task.completion());
+
+        let RenderTarget::Surface {
+            swapchain: Some(swapchain),
+            ..
+        } = &self.target
+        else {
+            return Ok(());
+        };
         let raw_sc = swapchain.handle();
 
-        let present_result = submit_task.then_present(self.queue.clone(), raw_sc, image_index);
+        let present_result =
+            submit_task.then_present(self.queues.graphics().clone(), raw_sc, image_index);
 
         match present_result {
-            Ok(suboptimal) => {
-                if suboptimal {
-                    return;
-                }
+            Ok(false) => Ok(()),
+            Ok(true) | Err(GPUTaskError::Present(vk::Result::ERROR_OUT_OF_DATE_KHR)) => {
+                self.target.mark_out_of_date();
+                Ok(())
             }
-            Err(_) => {
-                return;
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Records and submits the frame rendering to the acquired swapchain image
+    fn submit_swapchain_frame(
+        &mut self,
+        frame: FrameHandle,
+        image_index: u32,
+        acquire_semaphore: vk::Semaphore,
+    ) -> GraphicsResult<SubmitTask> {
+        let (swapchain, render_semaphore) = match &self.target {
+            RenderTarget::Surface {
+                swapchain: Some(swapchain),
+                render_semaphores,
+                ..
+            } => (swapchain, &render_semaphores[image_index as usize]),
+            _ => return Err(GraphicsError::Swapchain(vk::Result::ERROR_OUT_OF_DATE_KHR)),
+        };
+        let current_image = swapchain.image(image_index);
+
+        let mut encoder = self.frames.frame(frame).begin_commands()?;
+
+        let mut graph = RenderGraph::new();
+        let target = graph.import_image(
+            ImportedImage::new(
+                current_image.image(),
+                current_image.image_view(),
+                swapchain.extent(),
+            )
+            .initial_stage(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .final_access(ImageAccess::Present),
+        );
+        add_triangle_pass(
+            &mut graph,
+            target,
+            self.pipeline.as_ref(),
+            &self.vertex_buffer,
+        );
+        graph.execute(&mut encoder, self.frames.frame_mut(frame).transient_pool())?;
+
+        let current_command_buffer = encoder.end()?;
+
+        let signal_semaphore = render_semaphore.handle();
+        let wait_dst_stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+
+        let submit_runner = submit_task::submit(
+            self.queues.graphics().clone(),
+            SubmitInfo::new()
+                .command_buffers(vec![current_command_buffer])
+                .wait(acquire_semaphore, 0, wait_dst_stage_mask)
+                .signal(signal_semaphore, 0),
+            None,
+        );
+
+        Ok(task_from_runner(submit_runner)?)
+    }
+
+    /// Consumes the acquire semaphore of a frame that failed before its submission, so the
+    /// frame slot can signal it again. The image is never presented, the swapchain is
+    /// rebuilt to get it back.
+    fn release_acquired_image(&mut self, frame: FrameHandle, acquire_semaphore: vk::Semaphore) {
+        self.target.mark_out_of_date();
+
+        let submit_runner = submit_task::submit(
+            self.queues.graphics().clone(),
+            SubmitInfo::new().wait(acquire_semaphore, 0, vk::PipelineStageFlags::ALL_COMMANDS),
+            None,
+        );
+
+        match task_from_runner(submit_runner) {
+            Ok(task) => self.frames.end_frame(frame, task.completion()),
+            Err(e) => {
+                log::error!(target: "rust_engine::graphics", "Can't release acquire semaphore: {e}");
             }
         }
+    }
+
+    fn render_offscreen(&mut self) -> GraphicsResult<()> {
+        let frame = self.frames.begin_frame().map_err(GPUTaskError::Wait)?;
+
+        let target = match &self.target {
+            RenderTarget::Offscreen(target) => target,
+            _ => return Ok(()),
+        };
+
+        let mut encoder = self.frames.frame(frame).begin_commands()?;
+
+        let mut graph = RenderGraph::new();
+        let color = graph.import_image(ImportedImage::new(
+            target.image().image(),
+            target.image().image_view(),
+            target.extent(),
+        ));
+        let readback = graph.import_buffer(
+            target.readback_buffer().handle(),
+            Some(BufferAccess::HostRead),
+        );
+        add_triangle_pass(
+            &mut graph,
+            color,
+            self.pipeline.as_ref(),
+            &self.vertex_buffer,
+        );
+        graph
+            .add_pass("readback")
+            .image(color, ImageAccess::TransferSrc)
+            .buffer(readback, BufferAccess::TransferDst)
+            .record(|context| target.record_readback(context.encoder));
+        graph.execute(&mut encoder, self.frames.frame_mut(frame).transient_pool())?;
+
+        let current_command_buffer = encoder.end()?;
+
+        let submit_runner = submit_task::submit(
+            self.queues.graphics().clone(),
+            SubmitInfo::new().command_buffers(vec![current_command_buffer]),
+            None,
+        );
+
+        let submit_task = task_from_runner(submit_runner)?;
+        self.frames.end_frame(frame, submit_task.completion());
+
+        // The readback has to finish before `read_pixels`
+        submit_task.wait()?;
 
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        Ok(())
     }
 }
 
-fn create_instance(window: &winit::window::Window) -> Rc<Instance> {
+/// Adds the pass drawing the default triangle into `target`
+fn add_triangle_pass<'a>(
+    graph: &mut RenderGraph<'a>,
+    target: ImageHandle,
+    pipeline: Option<&'a Pipeline>,
+    vertex_buffer: &'a Buffer,
+) {
+    let vertex_buffer_handle = graph.import_buffer(vertex_buffer.handle(), None);
+    let clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: CLEAR_COLOR,
+        },
+    };
+
+    graph
+        .add_pass("triangle")
+        .color_attachment(target, LoadOp::Clear(clear_value))
+        .buffer(vertex_buffer_handle, BufferAccess::VertexInput)
+        .record(move |context| match pipeline {
+            Some(pipeline) => record_draw(context, pipeline, vertex_buffer),
+            None => Ok(()),
+        });
+}
+
+fn record_draw(
+    context: &mut PassContext<'_>,
+    pipeline: &Pipeline,
+    vertex_buffer: &Buffer,
+) -> Result<(), EncoderError> {
+    let extent = context.render_area;
+
+    let viewport = vk::Viewport::default()
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::default().extent(extent);
+
+    let push_constants = MvpPushConstants::default();
+
+    let encoder = &mut context.encoder;
+    encoder.bind_pipeline(pipeline)?;
+    encoder.set_viewport(viewport)?;
+    encoder.set_scissor(scissor)?;
+    encoder.push_constants(
+        MvpPushConstants::range().stage_flags,
+        0,
+        push_constants.as_bytes(),
+    )?;
+    encoder.bind_vertex_buffers(0, &[vertex_buffer], &[0])?;
+    encoder.draw(TRIANGLE.len() as u32, 1, 0, 0)
+}
+
+fn create_instance(surface_extensions: Vec<String>) -> GraphicsResult<Arc<Instance>> {
     let required_extensions: Vec<_> = {
-        let mut res = enumerate_required_extensions(window).unwrap();
+        let mut res = surface_extensions;
 
         if cfg!(feature = "gfx_debug_msg") {
             res.push(ash::ext::debug_utils::NAME.to_str().unwrap().to_string());
@@ -378,36 +760,75 @@ fn create_instance(window: &winit::window::Window) -> Rc<Instance> {
         .extensions(required_extensions)
         .layers(layers)
         .build()
-        .expect("Error while create instance")
+        .map_err(GraphicsError::Instance)
+}
+
+fn create_default_pipeline(
+    device: Arc<Device>,
+    color_format: vk::Format,
+) -> GraphicsResult<Pipeline> {
+    let [vertex, fragment] = DEFAULT_SHADERS;
+    let vertex = ShaderModule::from_file(device.clone(), vertex)?;
+    let fragment = ShaderModule::from_file(device.clone(), fragment)?;
+
+    let pipeline =
+        PipelineBuilder::default_pipeline(&vertex, &fragment, color_format).build(device)?;
+    gfx_debug_exec!({
+        pipeline.set_object_name("default pipeline");
+        pipeline.layout().set_object_name("default pipeline layout");
+    });
+
+    Ok(pipeline)
 }
 
 #[allow(clippy::too_many_arguments)]
+/// Returns `None` if the surface has no area
 fn create_swapchain(
-    device: Rc<Device>,
-    surface: &Surface,
+    device: Arc<Device>,
+    surface: &Arc<Surface>,
+    config: &SwapchainConfig,
     extent: vk::Extent2D,
     old_swapchain: Option<vk::SwapchainKHR>,
-) -> Swapchain {
-    device.wait_idle().unwrap();
+) -> GraphicsResult<Option<Swapchain>> {
+    let capabilities = device
+        .get_surface_capabilities(surface)
+        .map_err(GraphicsError::Surface)?;
+    let is_empty = |extent: vk::Extent2D| extent.width == 0 || extent.height == 0;
+
+    if is_empty(extent) {
+        return Ok(None);
+    }
 
-    let capabilities = device.get_surface_capabilities(&surface);
-    let present_mode = {
-        let modes = device.get_surface_present_modes(&surface);
+    let extent = swapchain::choose_extent(&capabilities, extent);
 
-        modes
-            .into_iter()
-            .find(|x| *x == vk::PresentModeKHR::MAILBOX || *x == vk::PresentModeKHR::IMMEDIATE)
-            .unwrap()
-    };
+    if is_empty(extent) {
+        return Ok(None);
+    }
 
-    let image_format = device
-        .get_surface_formats(&surface)
-        .into_iter()
-        .find(|x| {
-            x.format == vk::Format::B8G8R8A8_SRGB
-                && x.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        })
-        .unwrap();
+    let present_modes = device
+        .get_surface_present_modes(surface)
+        .map_err(GraphicsError::Surface)?;
+    let present_mode = config.choose_present_mode(&present_modes);
+    let formats = device
+        .get_surface_formats(surface)
+        .map_err(GraphicsError::Surface)?;
+    let image_format = config
+        .choose_surface_format(&formats)
+        .ok_or(GraphicsError::Surface(
+            vk::Result::ERROR_FORMAT_NOT_SUPPORTED,
+        ))?;
+    let min_image_count = config.choose_image_count(&capabilities);
+
+    log::info!(
+        target: "rust_engine::graphics",
+        "Swapchain {}x{}: {:?} {:?}, {:?}, {} images",
+        extent.width,
+        extent.height,
+        image_format.format,
+        image_format.color_space,
+        present_mode,
+        min_image_count,
+    );
 
     let image_description = SwapchainImageDescription {
         format: image_format.format,
@@ -418,22 +839,234 @@ fn create_swapchain(
         image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
     };
 
-    let swapchain = {
-        Swapchain::new(
-            device.clone(),
-            &surface,
-            SwapchainDescription {
-                image_description,
-                present_mode,
-                min_image_count: capabilities.min_image_count + 1,
-                pre_transform: capabilities.current_transform,
-                composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
-                old_swapchain,
-                ..Default::default()
-            },
-        )
-        .expect("Error while create swapchain")
-    };
+    Swapchain::new(
+        device.clone(),
+        surface.clone(),
+        SwapchainDescription {
+            image_description,
+            present_mode,
+            min_image_count,
+            pre_transform: capabilities.current_transform,
+            composite_alpha: swapchain::choose_composite_alpha(&capabilities),
+            old_swapchain,
+        },
+    )
+    .map(Some)
+    .map_err(GraphicsError::Swapchain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objects_are_thread_safe() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Instance>();
+        assert_send_sync::<Device>();
+        assert_send_sync::<Queue>();
+        assert_send_sync::<Surface>();
+        assert_send_sync::<Swapchain>();
+        assert_send_sync::<Buffer>();
+        assert_send_sync::<texture::Image>();
+        assert_send_sync::<Pipeline>();
+        assert_send_sync::<Semaphore>();
+        assert_send_sync::<sync::fence::Fence>();
+        assert_send_sync::<sync::timeline::Timeline>();
+        assert_send_sync::<descriptor::allocator::DescriptorAllocator>();
+        assert_send_sync::<command::ThreadCommandPools>();
+    }
+
+    #[test]
+    fn test_parallel_secondary_recording() {
+        let mut graphics_state = GraphicsState::new_offscreen(vk::Extent2D {
+            width: 16,
+            height: 16,
+        })
+        .unwrap();
+
+        let frame = graphics_state.frames.begin_frame().unwrap();
+        let pools = graphics_state.frames.frame(frame).secondary_pools();
+        let pipeline = graphics_state.pipeline.as_ref().unwrap();
+        let inheritance = command::SecondaryInheritance::new()
+            .color_formats(vec![graphics_state.pipeline_color_format]);
+
+        let secondaries: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let pool = pools.current().unwrap();
+                        let command_buffer = pool.lock().unwrap().secondary().unwrap();
+
+                        let mut encoder = command::CommandEncoder::begin_secondary(
+                            graphics_state.device.clone(),
+                            command_buffer,
+                            &inheritance,
+                        )
+                        .unwrap();
+
+                        assert_eq!(encoder.draw(3, 1, 0, 0), Err(EncoderError::NoPipeline));
+                        assert_eq!(
+                            encoder.bind_vertex_buffers(0, &[], &[0]),
+                            Err(EncoderError::VertexBufferOffsets {
+                                buffers: 0,
+                                offsets: 1
+                            })
+                        );
+                        encoder.bind_pipeline(pipeline).unwrap();
+                        encoder.draw(3, 1, 0, 0).unwrap();
+                        assert_eq!(
+                            encoder.end_rendering(),
+                            Err(EncoderError::InheritedRendering)
+                        );
+
+                        encoder.end().unwrap();
+                        encoder
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        assert_eq!(pools.len(), 4);
+        assert!(secondaries
+            .iter()
+            .all(|encoder| encoder.state() == command::EncoderState::Ended));
+
+        // The worker threads are gone, their pools are dropped once a reset finds them unused
+        pools.reset().unwrap();
+        assert_eq!(pools.len(), 4);
+        pools.reset().unwrap();
+        assert!(pools.is_empty());
+    }
+
+    #[test]
+    fn test_offscreen_readback() {
+        let extent = vk::Extent2D {
+            width: 64,
+            height: 32,
+        };
+
+        let mut graphics_state = GraphicsState::new_offscreen(extent).unwrap();
+        graphics_state.render().unwrap();
+
+        let pixels = graphics_state.read_pixels().unwrap().unwrap();
+
+        assert_eq!(pixels.len(), (extent.width * extent.height * 4) as usize);
 
-    swapchain
+        let pixel_at = |x: u32, y: u32| {
+            let offset = ((y * extent.width + x) * 4) as usize;
+            &pixels[offset..offset + 4]
+        };
+
+        let expected = CLEAR_COLOR.map(|c| (c * 255.0).round() as i32);
+        let is_clear = |pixel: &[u8]| {
+            pixel
+                .iter()
+                .zip(expected)
+                .all(|(channel, expected)| (*channel as i32 - expected).abs() <= 1)
+        };
+
+        assert!(is_clear(pixel_at(0, 0)));
+        assert!(!is_clear(pixel_at(extent.width / 2, extent.height / 2)));
+
+        graphics_state
+            .resize_extent(vk::Extent2D::default())
+            .unwrap();
+        graphics_state.render().unwrap();
+        assert_eq!(
+            graphics_state.read_pixels().unwrap().unwrap().len(),
+            pixels.len()
+        );
+    }
+
+    #[test]
+    fn test_headless_render() {
+        let extent = vk::Extent2D {
+            width: 64,
+            height: 32,
+        };
+
+        let mut graphics_state = GraphicsState::new_headless(extent).unwrap();
+
+        for _ in 0..graphics_state.frames_in_flight() * 2 {
+            graphics_state.render().unwrap();
+        }
+
+        assert!(graphics_state.read_pixels().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_swapchain_recreation() {
+        let extent = vk::Extent2D {
+            width: 64,
+            height: 32,
+        };
+
+        let mut graphics_state = GraphicsState::new_headless(extent).unwrap();
+        graphics_state.render().unwrap();
+        assert_eq!(graphics_state.swapchain().unwrap().extent(), extent);
+
+        // A resize marks the swapchain out of date, like an `OUT_OF_DATE` present would
+        let resized = vk::Extent2D {
+            width: 96,
+            height: 80,
+        };
+        graphics_state.resize_extent(resized).unwrap();
+
+        for _ in 0..graphics_state.frames_in_flight() {
+            graphics_state.render().unwrap();
+        }
+
+        assert_eq!(graphics_state.swapchain().unwrap().extent(), resized);
+        assert_eq!(graphics_state.frames.current_index(), 1);
+
+        // Minimized windows skip frames until they have an area again
+        graphics_state
+            .resize_extent(vk::Extent2D::default())
+            .unwrap();
+        graphics_state.render().unwrap();
+        assert_eq!(graphics_state.frames.current_index(), 1);
+
+        graphics_state.resize_extent(extent).unwrap();
+        graphics_state.render().unwrap();
+        assert_eq!(graphics_state.swapchain().unwrap().extent(), extent);
+        assert_eq!(graphics_state.frames.current_index(), 2);
+    }
+
+    #[test]
+    fn test_task_chain() {
+        let graphics_state = GraphicsState::new_offscreen(vk::Extent2D {
+            width: 16,
+            height: 16,
+        })
+        .unwrap();
+        let queue = graphics_state.queues.graphics().clone();
+
+        let submit = || {
+            task_from_runner(submit_task::submit(queue.clone(), SubmitInfo::new(), None)).unwrap()
+        };
+
+        let chain = submit()
+            .join(submit())
+            .then_execute_on(queue.clone())
+            .then_submit(SubmitInfo::new())
+            .unwrap()
+            .then_submit(SubmitInfo::new())
+            .unwrap();
+
+        chain.wait().unwrap();
+
+        assert_eq!(chain.completion()[0].value(), queue.timeline().last_value());
+        assert!(chain.previous().completion()[0].is_reached().unwrap());
+        assert_eq!(
+            chain.then_present(queue.clone(), vk::SwapchainKHR::null(), 0),
+            Err(GPUTaskError::NoPresentSemaphore)
+        );
+    }
 }
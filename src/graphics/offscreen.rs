@@ -0,0 +1,90 @@
+use super::buffer::{Buffer, BufferDescription, MemoryLocation};
+use super::command::{CommandEncoder, EncoderError};
+use super::device::Device;
+use super::texture::{Image, ImageDescription};
+use crate::gfx_debug_exec;
+use crate::utils::IntoExtent3D;
+use ash::prelude::VkResult;
+use ash::vk;
+use std::sync::Arc;
+
+/// Color target used when rendering without a display. Every frame is copied into a
+/// host visible buffer so the result can be read back as tightly packed RGBA bytes.
+#[derive(Debug)]
+pub struct OffscreenTarget {
+    image: Image,
+    readback_buffer: Buffer,
+    device: Arc<Device>,
+}
+
+impl OffscreenTarget {
+    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+    pub fn new(device: Arc<Device>, extent: vk::Extent2D) -> VkResult<Self> {
+        let description = ImageDescription::image2d()
+            .extent(extent.into_extent3d())
+            .format(Self::FORMAT)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC);
+
+        let image = Image::new(device.clone(), &description)?;
+        gfx_debug_exec!(image.set_object_name("offscreen color"));
+
+        let readback_description = BufferDescription::new()
+            .size(extent.width as u64 * extent.height as u64 * 4)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .location(MemoryLocation::Readback);
+
+        let readback_buffer = Buffer::new(device.clone(), &readback_description)?;
+        gfx_debug_exec!(readback_buffer.set_object_name("offscreen readback"));
+
+        Ok(Self {
+            image,
+            readback_buffer,
+            device,
+        })
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        let extent = self.image.extent();
+
+        vk::Extent2D {
+            width: extent.width,
+            height: extent.height,
+        }
+    }
+
+    pub fn readback_buffer(&self) -> &Buffer {
+        &self.readback_buffer
+    }
+
+    /// Records the copy of the rendered image into the readback buffer.
+    /// The image is expected to be in `TRANSFER_SRC_OPTIMAL` layout, making the copy
+    /// visible to the host is up to the caller.
+    pub fn record_readback(&self, encoder: &mut CommandEncoder) -> Result<(), EncoderError> {
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(self.image.extent());
+
+        encoder.copy_image_to_buffer(
+            self.image.image(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            &self.readback_buffer,
+            std::slice::from_ref(&region),
+        )
+    }
+
+    /// Returns the last frame copied by [`OffscreenTarget::record_readback`] as RGBA bytes.
+    /// The caller must make sure the copy has finished on the GPU.
+    pub fn read_pixels(&self) -> VkResult<Vec<u8>> {
+        self.readback_buffer.read_bytes()
+    }
+}
@@ -0,0 +1,259 @@
+use ash::prelude::VkResult;
+use ash::vk;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+const FILE_MAGIC: [u8; 4] = *b"LPPC";
+const FILE_VERSION: u32 = 1;
+/// magic + version + data length + data hash
+const FILE_HEADER_SIZE: usize = 4 + 4 + 8 + 8;
+/// Size of `VkPipelineCacheHeaderVersionOne`
+const VK_HEADER_SIZE: usize = 16 + 16;
+
+#[derive(Debug)]
+pub enum PipelineCacheError {
+    Io(std::io::Error),
+    Vk(vk::Result),
+    Corrupt(&'static str),
+    DeviceMismatch,
+}
+
+impl Display for PipelineCacheError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineCacheError::Io(err) => write!(f, "{err}"),
+            PipelineCacheError::Vk(err) => write!(f, "{err}"),
+            PipelineCacheError::Corrupt(reason) => write!(f, "corrupt pipeline cache: {reason}"),
+            PipelineCacheError::DeviceMismatch => {
+                write!(f, "pipeline cache was created by another device or driver")
+            }
+        }
+    }
+}
+
+impl Error for PipelineCacheError {}
+
+pub fn default_pipeline_cache_dir() -> PathBuf {
+    std::env::temp_dir().join(env!("CARGO_PKG_NAME"))
+}
+
+/// File name unique to the device and driver build
+pub fn pipeline_cache_file_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    let uuid: String = properties
+        .pipeline_cache_uuid
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    format!(
+        "pipeline_{:04x}_{:04x}_{uuid}.bin",
+        properties.vendor_id, properties.device_id
+    )
+}
+
+/// `vk::PipelineCache` owned by the device, persisted between runs.
+/// Files are wrapped in a small header with a hash of the data, and the Vulkan cache header
+/// is checked against the device before the data is handed to the driver.
+#[derive(Debug)]
+pub struct PipelineCache {
+    handle: vk::PipelineCache,
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// Loads the cache from `dir`. Missing, corrupt or mismatched files start an empty cache.
+    pub fn load(
+        device: &ash::Device,
+        properties: &vk::PhysicalDeviceProperties,
+        dir: Option<&Path>,
+    ) -> VkResult<Self> {
+        let path = dir.map(|dir| dir.join(pipeline_cache_file_name(properties)));
+
+        let file = path.as_ref().and_then(|path| match std::fs::read(path) {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                log::warn!(target: "rust_engine::graphics", "Can't read {}: {e}", path.display());
+                None
+            }
+        });
+
+        let initial_data = match file.as_deref().map(|file| decode(file, properties)) {
+            Some(Ok(data)) => data,
+            Some(Err(e)) => {
+                log::warn!(target: "rust_engine::graphics", "Ignoring pipeline cache: {e}");
+                &[]
+            }
+            None => &[],
+        };
+
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(initial_data);
+        let handle = unsafe { device.create_pipeline_cache(&create_info, None)? };
+
+        Ok(Self { handle, path })
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.handle
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Writes the cache to its file. The file is replaced atomically,
+    /// so a crash while saving never leaves a half written cache behind.
+    pub fn save(&self, device: &ash::Device) -> Result<(), PipelineCacheError> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+
+        let data = unsafe { device.get_pipeline_cache_data(self.handle) }
+            .map_err(PipelineCacheError::Vk)?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(PipelineCacheError::Io)?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, encode(&data)).map_err(PipelineCacheError::Io)?;
+        std::fs::rename(&temp_path, path).map_err(PipelineCacheError::Io)
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe { device.destroy_pipeline_cache(self.handle, None) };
+        self.handle = vk::PipelineCache::null();
+    }
+}
+
+fn hash(data: &[u8]) -> u64 {
+    // FNV-1a
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn encode(data: &[u8]) -> Vec<u8> {
+    let mut file = Vec::with_capacity(FILE_HEADER_SIZE + data.len());
+    file.extend_from_slice(&FILE_MAGIC);
+    file.extend_from_slice(&FILE_VERSION.to_le_bytes());
+    file.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    file.extend_from_slice(&hash(data).to_le_bytes());
+    file.extend_from_slice(data);
+
+    file
+}
+
+/// Returns the Vulkan cache data stored in `file` if it is intact and made for this device
+fn decode<'a>(
+    file: &'a [u8],
+    properties: &vk::PhysicalDeviceProperties,
+) -> Result<&'a [u8], PipelineCacheError> {
+    let read_u32 = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    };
+    let read_u64 = |bytes: &[u8], offset: usize| {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    };
+
+    if file.len() < FILE_HEADER_SIZE || file[0..4] != FILE_MAGIC {
+        return Err(PipelineCacheError::Corrupt("bad file header"));
+    }
+    if read_u32(file, 4) != FILE_VERSION {
+        return Err(PipelineCacheError::Corrupt("unsupported file version"));
+    }
+
+    let data = &file[FILE_HEADER_SIZE..];
+    if read_u64(file, 8) != data.len() as u64 {
+        return Err(PipelineCacheError::Corrupt("truncated data"));
+    }
+    if read_u64(file, 16) != hash(data) {
+        return Err(PipelineCacheError::Corrupt("hash mismatch"));
+    }
+
+    // VkPipelineCacheHeaderVersionOne is written in host byte order
+    let read_u32 = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    if data.len() < VK_HEADER_SIZE || (read_u32(0) as usize) < VK_HEADER_SIZE {
+        return Err(PipelineCacheError::Corrupt("bad Vulkan cache header"));
+    }
+    if read_u32(4) != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err(PipelineCacheError::DeviceMismatch);
+    }
+    if read_u32(8) != properties.vendor_id
+        || read_u32(12) != properties.device_id
+        || data[16..32] != properties.pipeline_cache_uuid
+    {
+        return Err(PipelineCacheError::DeviceMismatch);
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2484,
+            pipeline_cache_uuid: [7; 16],
+            ..Default::default()
+        }
+    }
+
+    fn vulkan_cache_data(properties: &vk::PhysicalDeviceProperties) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(VK_HEADER_SIZE as u32).to_ne_bytes());
+        data.extend_from_slice(
+            &(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_ne_bytes(),
+        );
+        data.extend_from_slice(&properties.vendor_id.to_ne_bytes());
+        data.extend_from_slice(&properties.device_id.to_ne_bytes());
+        data.extend_from_slice(&properties.pipeline_cache_uuid);
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let properties = properties();
+        let data = vulkan_cache_data(&properties);
+
+        let file = encode(&data);
+
+        assert_eq!(decode(&file, &properties).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn test_decode_rejects_corrupt_and_mismatched() {
+        let properties = properties();
+        let file = encode(&vulkan_cache_data(&properties));
+
+        let mut corrupt = file.clone();
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        assert!(matches!(
+            decode(&corrupt, &properties),
+            Err(PipelineCacheError::Corrupt(_))
+        ));
+        assert!(matches!(
+            decode(&file[..file.len() - 1], &properties),
+            Err(PipelineCacheError::Corrupt(_))
+        ));
+        assert!(matches!(
+            decode(&[], &properties),
+            Err(PipelineCacheError::Corrupt(_))
+        ));
+
+        let other_driver = vk::PhysicalDeviceProperties {
+            pipeline_cache_uuid: [8; 16],
+            ..properties
+        };
+        assert!(matches!(
+            decode(&file, &other_driver),
+            Err(PipelineCacheError::DeviceMismatch)
+        ));
+    }
+}
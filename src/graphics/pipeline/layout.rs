@@ -0,0 +1,91 @@
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
+use crate::graphics::descriptor::DeviceDescriptorFns;
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use crate::graphics::shader::reflection::ShaderReflection;
+use crate::{gfx_debug_exec, gfx_debug_log};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct PipelineLayout {
+    handle: vk::PipelineLayout,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    device: Arc<Device>,
+}
+
+impl PipelineLayout {
+    pub fn new(
+        device: Arc<Device>,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> VkResult<Self> {
+        let create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+
+        let handle = device.create(&create_info)?;
+
+        Ok(Self {
+            handle,
+            push_constant_ranges: push_constant_ranges.to_vec(),
+            set_layouts: set_layouts.to_vec(),
+            device,
+        })
+    }
+
+    /// Creates the layout from merged shader reflection.
+    /// Descriptor set layouts come from the device's layout cache.
+    pub fn from_reflection(device: Arc<Device>, reflection: &ShaderReflection) -> VkResult<Self> {
+        let set_layouts = reflection
+            .set_layout_bindings()
+            .iter()
+            .map(|bindings| device.get_descriptor_set_layout(bindings))
+            .collect::<VkResult<Vec<_>>>()?;
+
+        let push_constant_ranges: Vec<_> = reflection.push_constant_range().into_iter().collect();
+
+        Self::new(device, &set_layouts, &push_constant_ranges)
+    }
+
+    pub fn handle(&self) -> vk::PipelineLayout {
+        self.handle
+    }
+
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.push_constant_ranges
+    }
+
+    pub fn set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+        &self.set_layouts
+    }
+}
+
+impl Drop for PipelineLayout {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(PipelineLayout::drop()));
+        self.device.destroy(self.handle);
+    }
+}
+
+impl DeviceCreateExtend<vk::PipelineLayoutCreateInfo<'_>, vk::PipelineLayout> for Device {
+    fn create(
+        &self,
+        create_info: &vk::PipelineLayoutCreateInfo<'_>,
+    ) -> VkResult<vk::PipelineLayout> {
+        unsafe { self.handle().create_pipeline_layout(create_info, None) }
+    }
+}
+
+impl DeviceDestroyExtend<vk::PipelineLayout> for Device {
+    fn destroy(&self, vk_struct: vk::PipelineLayout) {
+        unsafe {
+            self.handle().destroy_pipeline_layout(vk_struct, None);
+        }
+    }
+}
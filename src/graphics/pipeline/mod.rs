@@ -0,0 +1,438 @@
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use crate::graphics::shader::reflection::{ReflectionError, ShaderReflection};
+use crate::graphics::shader::ShaderModule;
+use crate::{gfx_debug_exec, gfx_debug_log};
+use ash::prelude::VkResult;
+use ash::vk;
+use layout::PipelineLayout;
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use vertex::Vertex;
+
+pub mod cache;
+pub mod layout;
+pub mod vertex;
+
+#[derive(Debug)]
+pub struct Pipeline {
+    handle: vk::Pipeline,
+    layout: Arc<PipelineLayout>,
+    device: Arc<Device>,
+}
+
+impl Pipeline {
+    pub fn handle(&self) -> vk::Pipeline {
+        self.handle
+    }
+
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+
+    /// Names the pipeline. The layout can be shared and is named on its own.
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(Pipeline::drop()));
+        self.device.destroy(self.handle);
+    }
+}
+
+#[derive(Debug)]
+pub enum PipelineBuildError {
+    Reflection(ReflectionError),
+    LayoutCreate(vk::Result),
+    PipelineCreate(vk::Result),
+}
+
+impl Display for PipelineBuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineBuildError::Reflection(err) => write!(f, "{err}"),
+            PipelineBuildError::LayoutCreate(err) => write!(f, "{err}"),
+            PipelineBuildError::PipelineCreate(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for PipelineBuildError {}
+
+impl From<ReflectionError> for PipelineBuildError {
+    fn from(value: ReflectionError) -> Self {
+        PipelineBuildError::Reflection(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    Alpha,
+    Additive,
+}
+
+impl BlendMode {
+    fn to_vk(self) -> vk::PipelineColorBlendAttachmentState {
+        let state = vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+
+        match self {
+            BlendMode::Opaque => state.blend_enable(false),
+            BlendMode::Alpha => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            BlendMode::Additive => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DepthState {
+    pub format: vk::Format,
+    pub test: bool,
+    pub write: bool,
+    pub compare_op: vk::CompareOp,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            format: vk::Format::D32_SFLOAT,
+            test: true,
+            write: true,
+            compare_op: vk::CompareOp::LESS,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ShaderStage {
+    stage: vk::ShaderStageFlags,
+    module: vk::ShaderModule,
+    entry_point: CString,
+    reflection: Option<ShaderReflection>,
+}
+
+/// Builds graphics pipelines for dynamic rendering. Viewport and scissor are always dynamic.
+///
+/// Stages added with [`PipelineBuilder::shader_module`] are reflected: vertex input is validated
+/// against the shader and, without explicit set layouts or push constant ranges,
+/// the pipeline layout is derived from the shaders.
+#[derive(Debug)]
+pub struct PipelineBuilder {
+    stages: Vec<ShaderStage>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    blend_mode: BlendMode,
+    depth: Option<DepthState>,
+    color_formats: Vec<vk::Format>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            stages: Vec::new(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend_mode: BlendMode::default(),
+            depth: None,
+            color_formats: Vec::new(),
+            set_layouts: Vec::new(),
+            push_constant_ranges: Vec::new(),
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder for `res/shaders/default.vert.glsl` and `res/shaders/default.frag.glsl`
+    pub fn default_pipeline(
+        vertex_module: &ShaderModule,
+        fragment_module: &ShaderModule,
+        color_format: vk::Format,
+    ) -> Self {
+        Self::new()
+            .shader_module(vertex_module)
+            .shader_module(fragment_module)
+            .vertex_input::<vertex::DefaultVertex>(0)
+            .color_formats(vec![color_format])
+    }
+
+    pub fn shader(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+        entry_point: &str,
+    ) -> Self {
+        self.stages.push(ShaderStage {
+            stage,
+            module,
+            entry_point: CString::new(entry_point).unwrap(),
+            reflection: None,
+        });
+        self
+    }
+
+    /// Adds the `main` entry point of `module` together with its reflection
+    pub fn shader_module(mut self, module: &ShaderModule) -> Self {
+        self.stages.push(ShaderStage {
+            stage: module.stage(),
+            module: module.handle(),
+            entry_point: CString::new("main").unwrap(),
+            reflection: Some(module.reflection().clone()),
+        });
+        self
+    }
+
+    pub fn vertex_input<V: Vertex>(self, binding: u32) -> Self {
+        self.vertex_binding::<V>(binding, vk::VertexInputRate::VERTEX)
+    }
+
+    pub fn instance_input<V: Vertex>(self, binding: u32) -> Self {
+        self.vertex_binding::<V>(binding, vk::VertexInputRate::INSTANCE)
+    }
+
+    fn vertex_binding<V: Vertex>(mut self, binding: u32, input_rate: vk::VertexInputRate) -> Self {
+        self.vertex_bindings.push(
+            vk::VertexInputBindingDescription::default()
+                .binding(binding)
+                .stride(std::mem::size_of::<V>() as u32)
+                .input_rate(input_rate),
+        );
+        self.vertex_attributes.extend(
+            V::attributes()
+                .into_iter()
+                .map(|attribute| attribute.binding(binding)),
+        );
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn depth(mut self, depth: DepthState) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    pub fn color_formats(mut self, color_formats: Vec<vk::Format>) -> Self {
+        self.color_formats = color_formats;
+        self
+    }
+
+    pub fn set_layouts(mut self, set_layouts: Vec<vk::DescriptorSetLayout>) -> Self {
+        self.set_layouts = set_layouts;
+        self
+    }
+
+    pub fn push_constant_range(mut self, range: vk::PushConstantRange) -> Self {
+        self.push_constant_ranges.push(range);
+        self
+    }
+
+    /// Merged reflection of all reflected stages
+    fn reflection(&self) -> Result<Option<ShaderReflection>, ReflectionError> {
+        let mut reflections = self
+            .stages
+            .iter()
+            .filter_map(|stage| stage.reflection.as_ref())
+            .peekable();
+
+        if reflections.peek().is_none() {
+            return Ok(None);
+        }
+
+        ShaderReflection::merge(reflections).map(Some)
+    }
+
+    pub fn build(self, device: Arc<Device>) -> Result<Pipeline, PipelineBuildError> {
+        let reflection = self.reflection()?;
+
+        let layout = match reflection {
+            Some(reflection)
+                if self.set_layouts.is_empty() && self.push_constant_ranges.is_empty() =>
+            {
+                PipelineLayout::from_reflection(device.clone(), &reflection)
+            }
+            _ => PipelineLayout::new(
+                device.clone(),
+                &self.set_layouts,
+                &self.push_constant_ranges,
+            ),
+        }
+        .map_err(PipelineBuildError::LayoutCreate)?;
+
+        self.build_with_layout(device, Arc::new(layout))
+    }
+
+    pub fn build_with_layout(
+        self,
+        device: Arc<Device>,
+        layout: Arc<PipelineLayout>,
+    ) -> Result<Pipeline, PipelineBuildError> {
+        if let Some(reflection) = self.reflection()? {
+            reflection.validate_vertex_input(&self.vertex_attributes)?;
+            reflection.validate_push_constants(layout.push_constant_ranges())?;
+        }
+
+        let stages: Vec<_> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(stage.stage)
+                    .module(stage.module)
+                    .name(&stage.entry_point)
+            })
+            .collect();
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes);
+
+        let input_assembly =
+            vk::PipelineInputAssemblyStateCreateInfo::default().topology(self.topology);
+
+        let viewport = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(1.0);
+
+        let multisample = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let depth = self.depth.unwrap_or(DepthState {
+            format: vk::Format::UNDEFINED,
+            test: false,
+            write: false,
+            compare_op: vk::CompareOp::ALWAYS,
+        });
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(depth.test)
+            .depth_write_enable(depth.write)
+            .depth_compare_op(depth.compare_op);
+
+        let blend_attachments: Vec<_> = self
+            .color_formats
+            .iter()
+            .map(|_| self.blend_mode.to_vk())
+            .collect();
+
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(depth.format);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(layout.handle())
+            .push_next(&mut rendering_info);
+
+        let handle = device
+            .create(&create_info)
+            .map_err(PipelineBuildError::PipelineCreate)?;
+
+        Ok(Pipeline {
+            handle,
+            layout,
+            device,
+        })
+    }
+}
+
+impl DeviceCreateExtend<vk::GraphicsPipelineCreateInfo<'_>, vk::Pipeline> for Device {
+    fn create(&self, create_info: &vk::GraphicsPipelineCreateInfo<'_>) -> VkResult<vk::Pipeline> {
+        unsafe {
+            self.handle()
+                .create_graphics_pipelines(
+                    self.pipeline_cache(),
+                    std::slice::from_ref(create_info),
+                    None,
+                )
+                .map(|pipelines| pipelines[0])
+                .map_err(|(_, e)| e)
+        }
+    }
+}
+
+impl DeviceDestroyExtend<vk::Pipeline> for Device {
+    fn destroy(&self, vk_struct: vk::Pipeline) {
+        self.defer_destroy(move |device| unsafe {
+            device.handle().destroy_pipeline(vk_struct, None);
+        });
+    }
+}
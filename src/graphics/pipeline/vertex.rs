@@ -0,0 +1,89 @@
+use ash::vk;
+
+/// Vertex layout of a single vertex buffer binding.
+/// The `binding` field of the returned attributes is filled in by the pipeline builder.
+pub trait Vertex: Copy {
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription>;
+}
+
+/// Vertex layout expected by `res/shaders/default.vert.glsl`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DefaultVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl Vertex for DefaultVertex {
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription::default()
+                .location(0)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(std::mem::offset_of!(DefaultVertex, position) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .location(1)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(std::mem::offset_of!(DefaultVertex, color) as u32),
+        ]
+    }
+}
+
+/// Push constant block `MVP` of `res/shaders/default.vert.glsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MvpPushConstants {
+    pub proj: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub model: [[f32; 4]; 4],
+}
+
+impl MvpPushConstants {
+    pub const IDENTITY: [[f32; 4]; 4] = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                (self as *const Self) as *const u8,
+                std::mem::size_of::<Self>(),
+            )
+        }
+    }
+
+    pub fn range() -> vk::PushConstantRange {
+        vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(std::mem::size_of::<Self>() as u32)
+    }
+}
+
+impl Default for MvpPushConstants {
+    fn default() -> Self {
+        Self {
+            proj: Self::IDENTITY,
+            view: Self::IDENTITY,
+            model: Self::IDENTITY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_layouts() {
+        assert_eq!(MvpPushConstants::range().size, 3 * 16 * 4);
+
+        let attributes = DefaultVertex::attributes();
+        assert_eq!(attributes[0].offset, 0);
+        assert_eq!(attributes[1].offset, 12);
+        assert_eq!(std::mem::size_of::<DefaultVertex>(), 24);
+    }
+}
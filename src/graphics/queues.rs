@@ -0,0 +1,309 @@
+use super::device::Queue;
+use ash::prelude::VkResult;
+use ash::vk;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueueRole {
+    Graphics,
+    /// Async compute, runs next to graphics work when the device has a separate family
+    Compute,
+    /// Uploads and readbacks, ideally on the copy engine
+    Transfer,
+}
+
+/// Queue family used for each role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    pub compute: u32,
+    pub transfer: u32,
+}
+
+impl QueueFamilies {
+    /// Finds dedicated compute and transfer families. Roles without one share `graphics`.
+    pub fn discover(families: &[vk::QueueFamilyProperties], graphics: u32) -> Self {
+        let find = |required: vk::QueueFlags, excluded: vk::QueueFlags| {
+            families
+                .iter()
+                .position(|family| {
+                    family.queue_count > 0
+                        && family.queue_flags.contains(required)
+                        && !family.queue_flags.intersects(excluded)
+                })
+                .map(|index| index as u32)
+        };
+
+        let compute = find(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS).unwrap_or(graphics);
+
+        // A pure copy family first, then any non graphics family that can transfer
+        let transfer = find(
+            vk::QueueFlags::TRANSFER,
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+        )
+        .or_else(|| find(vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS))
+        .unwrap_or(graphics);
+
+        Self {
+            graphics,
+            compute,
+            transfer,
+        }
+    }
+
+    pub fn family(&self, role: QueueRole) -> u32 {
+        match role {
+            QueueRole::Graphics => self.graphics,
+            QueueRole::Compute => self.compute,
+            QueueRole::Transfer => self.transfer,
+        }
+    }
+
+    /// Distinct families, in the order their queues have to be requested
+    pub fn unique(&self) -> Vec<u32> {
+        let mut unique = vec![self.graphics];
+        for family in [self.compute, self.transfer] {
+            if !unique.contains(&family) {
+                unique.push(family);
+            }
+        }
+
+        unique
+    }
+}
+
+/// One queue per role. Roles that fall back to another family share its queue.
+#[derive(Debug, Clone)]
+pub struct Queues {
+    families: QueueFamilies,
+    graphics: Queue,
+    compute: Queue,
+    transfer: Queue,
+}
+
+impl Queues {
+    /// `queues` holds one queue for every family of [`QueueFamilies::unique`].
+    /// Fails with `ERROR_INITIALIZATION_FAILED` when a family has no queue.
+    pub fn new(families: QueueFamilies, queues: impl IntoIterator<Item = Queue>) -> VkResult<Self> {
+        let queues: Vec<_> = queues.into_iter().collect();
+        let queue = |family: u32| {
+            queues
+                .iter()
+                .find(|queue| queue.family_index() == family)
+                .cloned()
+                .ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)
+        };
+
+        Ok(Self {
+            graphics: queue(families.graphics)?,
+            compute: queue(families.compute)?,
+            transfer: queue(families.transfer)?,
+            families,
+        })
+    }
+
+    pub fn families(&self) -> QueueFamilies {
+        self.families
+    }
+
+    pub fn get(&self, role: QueueRole) -> &Queue {
+        match role {
+            QueueRole::Graphics => &self.graphics,
+            QueueRole::Compute => &self.compute,
+            QueueRole::Transfer => &self.transfer,
+        }
+    }
+
+    pub fn graphics(&self) -> &Queue {
+        &self.graphics
+    }
+
+    pub fn compute(&self) -> &Queue {
+        &self.compute
+    }
+
+    pub fn transfer(&self) -> &Queue {
+        &self.transfer
+    }
+
+    pub fn ownership_transfer(&self, src: QueueRole, dst: QueueRole) -> OwnershipTransfer {
+        OwnershipTransfer::new(self.families.family(src), self.families.family(dst))
+    }
+}
+
+/// Barriers that move an `EXCLUSIVE` resource between queue families.
+/// The release barrier is recorded on the source queue, the acquire barrier on the
+/// destination queue, and the acquiring submission waits on a semaphore signaled by
+/// the releasing one. Both barriers have to use the same layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnershipTransfer {
+    pub src_family: u32,
+    pub dst_family: u32,
+}
+
+impl OwnershipTransfer {
+    pub fn new(src_family: u32, dst_family: u32) -> Self {
+        Self {
+            src_family,
+            dst_family,
+        }
+    }
+
+    /// Families are the same, a regular barrier is enough
+    pub fn is_noop(&self) -> bool {
+        self.src_family == self.dst_family
+    }
+
+    pub fn buffer_release(
+        &self,
+        buffer: vk::Buffer,
+        src_access: vk::AccessFlags,
+    ) -> vk::BufferMemoryBarrier<'static> {
+        self.buffer_barrier(buffer)
+            .src_access_mask(src_access)
+            .dst_access_mask(vk::AccessFlags::empty())
+    }
+
+    pub fn buffer_acquire(
+        &self,
+        buffer: vk::Buffer,
+        dst_access: vk::AccessFlags,
+    ) -> vk::BufferMemoryBarrier<'static> {
+        self.buffer_barrier(buffer)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(dst_access)
+    }
+
+    pub fn image_release(
+        &self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier<'static> {
+        self.image_barrier(image, subresource_range, old_layout, new_layout)
+            .src_access_mask(src_access)
+            .dst_access_mask(vk::AccessFlags::empty())
+    }
+
+    pub fn image_acquire(
+        &self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        dst_access: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier<'static> {
+        self.image_barrier(image, subresource_range, old_layout, new_layout)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(dst_access)
+    }
+
+    /// Family indices are ignored when both sides are the same family
+    fn families(&self) -> (u32, u32) {
+        if self.is_noop() {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        } else {
+            (self.src_family, self.dst_family)
+        }
+    }
+
+    fn buffer_barrier(&self, buffer: vk::Buffer) -> vk::BufferMemoryBarrier<'static> {
+        let (src_family, dst_family) = self.families();
+
+        vk::BufferMemoryBarrier::default()
+            .buffer(buffer)
+            .size(vk::WHOLE_SIZE)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+    }
+
+    fn image_barrier(
+        &self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier<'static> {
+        let (src_family, dst_family) = self.families();
+
+        vk::ImageMemoryBarrier::default()
+            .image(image)
+            .subresource_range(subresource_range)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(queue_flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags,
+            queue_count: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_discover_families() {
+        let all = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let compute = vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+
+        let discrete = [
+            family(all),
+            family(compute),
+            family(vk::QueueFlags::TRANSFER),
+        ];
+        assert_eq!(
+            QueueFamilies::discover(&discrete, 0),
+            QueueFamilies {
+                graphics: 0,
+                compute: 1,
+                transfer: 2,
+            }
+        );
+
+        // No copy engine, transfers go to the async compute family
+        let families = QueueFamilies::discover(&discrete[..2], 0);
+        assert_eq!(families.transfer, 1);
+        assert_eq!(families.unique(), vec![0, 1]);
+
+        let integrated = QueueFamilies::discover(&[family(all)], 0);
+        assert_eq!(integrated.compute, 0);
+        assert_eq!(integrated.transfer, 0);
+        assert_eq!(integrated.unique(), vec![0]);
+    }
+
+    #[test]
+    fn test_ownership_transfer_barriers() {
+        let transfer = OwnershipTransfer::new(2, 0);
+        let release = transfer.buffer_release(vk::Buffer::null(), vk::AccessFlags::TRANSFER_WRITE);
+        let acquire = transfer.buffer_acquire(vk::Buffer::null(), vk::AccessFlags::SHADER_READ);
+
+        assert_eq!(
+            (
+                release.src_queue_family_index,
+                release.dst_queue_family_index
+            ),
+            (2, 0)
+        );
+        assert_eq!(
+            (
+                acquire.src_queue_family_index,
+                acquire.dst_queue_family_index
+            ),
+            (2, 0)
+        );
+        assert_eq!(release.dst_access_mask, vk::AccessFlags::empty());
+        assert_eq!(acquire.src_access_mask, vk::AccessFlags::empty());
+
+        let same = OwnershipTransfer::new(1, 1)
+            .buffer_release(vk::Buffer::null(), vk::AccessFlags::TRANSFER_WRITE);
+        assert_eq!(same.src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    }
+}
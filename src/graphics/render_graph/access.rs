@@ -0,0 +1,296 @@
+use ash::vk;
+
+/// How a pass uses an image. Determines the layout the image is transitioned to
+/// and the pipeline stages and accesses a barrier has to wait for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageAccess {
+    ColorAttachment,
+    DepthAttachment,
+    DepthAttachmentRead,
+    SampledFragment,
+    SampledCompute,
+    StorageRead,
+    StorageWrite,
+    TransferSrc,
+    TransferDst,
+    Present,
+}
+
+impl ImageAccess {
+    pub fn stage(self) -> vk::PipelineStageFlags {
+        match self {
+            ImageAccess::ColorAttachment => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ImageAccess::DepthAttachment | ImageAccess::DepthAttachmentRead => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            ImageAccess::SampledFragment => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ImageAccess::SampledCompute | ImageAccess::StorageRead | ImageAccess::StorageWrite => {
+                vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            ImageAccess::TransferSrc | ImageAccess::TransferDst => vk::PipelineStageFlags::TRANSFER,
+            ImageAccess::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        }
+    }
+
+    pub fn access(self) -> vk::AccessFlags {
+        match self {
+            ImageAccess::ColorAttachment => {
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            ImageAccess::DepthAttachment => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            ImageAccess::DepthAttachmentRead => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+            ImageAccess::SampledFragment
+            | ImageAccess::SampledCompute
+            | ImageAccess::StorageRead => vk::AccessFlags::SHADER_READ,
+            ImageAccess::StorageWrite => vk::AccessFlags::SHADER_WRITE,
+            ImageAccess::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            ImageAccess::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+            ImageAccess::Present => vk::AccessFlags::empty(),
+        }
+    }
+
+    pub fn layout(self) -> vk::ImageLayout {
+        match self {
+            ImageAccess::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageAccess::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageAccess::DepthAttachmentRead => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ImageAccess::SampledFragment | ImageAccess::SampledCompute => {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }
+            ImageAccess::StorageRead | ImageAccess::StorageWrite => vk::ImageLayout::GENERAL,
+            ImageAccess::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageAccess::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageAccess::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    pub fn usage(self) -> vk::ImageUsageFlags {
+        match self {
+            ImageAccess::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ImageAccess::DepthAttachment | ImageAccess::DepthAttachmentRead => {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            }
+            ImageAccess::SampledFragment | ImageAccess::SampledCompute => {
+                vk::ImageUsageFlags::SAMPLED
+            }
+            ImageAccess::StorageRead | ImageAccess::StorageWrite => vk::ImageUsageFlags::STORAGE,
+            ImageAccess::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+            ImageAccess::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+            ImageAccess::Present => vk::ImageUsageFlags::empty(),
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            ImageAccess::ColorAttachment
+                | ImageAccess::DepthAttachment
+                | ImageAccess::StorageWrite
+                | ImageAccess::TransferDst
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferAccess {
+    VertexInput,
+    IndexInput,
+    Uniform,
+    StorageRead,
+    StorageWrite,
+    TransferSrc,
+    TransferDst,
+    HostRead,
+}
+
+impl BufferAccess {
+    pub fn stage(self) -> vk::PipelineStageFlags {
+        match self {
+            BufferAccess::VertexInput | BufferAccess::IndexInput => {
+                vk::PipelineStageFlags::VERTEX_INPUT
+            }
+            BufferAccess::Uniform | BufferAccess::StorageRead => {
+                vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            BufferAccess::StorageWrite => vk::PipelineStageFlags::COMPUTE_SHADER,
+            BufferAccess::TransferSrc | BufferAccess::TransferDst => {
+                vk::PipelineStageFlags::TRANSFER
+            }
+            BufferAccess::HostRead => vk::PipelineStageFlags::HOST,
+        }
+    }
+
+    pub fn access(self) -> vk::AccessFlags {
+        match self {
+            BufferAccess::VertexInput => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            BufferAccess::IndexInput => vk::AccessFlags::INDEX_READ,
+            BufferAccess::Uniform => vk::AccessFlags::UNIFORM_READ,
+            BufferAccess::StorageRead => vk::AccessFlags::SHADER_READ,
+            BufferAccess::StorageWrite => vk::AccessFlags::SHADER_WRITE,
+            BufferAccess::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            BufferAccess::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+            BufferAccess::HostRead => vk::AccessFlags::HOST_READ,
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(self, BufferAccess::StorageWrite | BufferAccess::TransferDst)
+    }
+}
+
+/// Accesses that have to be made available by a barrier. Reads only need an execution dependency.
+pub(super) const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::SHADER_WRITE.as_raw()
+        | vk::AccessFlags::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags::HOST_WRITE.as_raw(),
+);
+
+/// Source scope of the barrier an access has to wait behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Dependency {
+    pub layout: vk::ImageLayout,
+    pub stage: vk::PipelineStageFlags,
+    /// Only the writes that have to be made available
+    pub access: vk::AccessFlags,
+}
+
+/// Accesses to a resource since its previous barrier, and the stages and accesses its last
+/// write has been made visible to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ResourceState {
+    pub layout: vk::ImageLayout,
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+    write_stage: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    visible_stage: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+}
+
+impl ResourceState {
+    pub fn new(layout: vk::ImageLayout, stage: vk::PipelineStageFlags) -> Self {
+        Self {
+            layout,
+            stage,
+            access: vk::AccessFlags::empty(),
+            write_stage: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            visible_stage: vk::PipelineStageFlags::empty(),
+            visible_access: vk::AccessFlags::empty(),
+        }
+    }
+
+    /// Returns what a barrier has to wait for, or `None` if the access can run without one.
+    /// Reads the last write is already visible to are merged, so a later write waits for all
+    /// of them. Reads from other stages or with other accesses wait for the write again.
+    pub fn transition(
+        &mut self,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+        write: bool,
+    ) -> Option<Dependency> {
+        let has_accesses = !self.access.is_empty();
+        let write_visible = self.write_access.is_empty()
+            || (self.visible_stage.contains(stage) && self.visible_access.contains(access));
+        let read_after_write = !write && !write_visible;
+        let needs_barrier = self.layout != layout || read_after_write || (write && has_accesses);
+
+        if !needs_barrier {
+            if has_accesses {
+                self.stage |= stage;
+                self.access |= access;
+            } else {
+                self.stage = stage;
+                self.access = access;
+            }
+            if write {
+                self.write_stage = stage;
+                self.write_access = access & WRITE_ACCESS;
+            }
+            return None;
+        }
+
+        let mut dependency = Dependency {
+            layout: self.layout,
+            stage: self.stage,
+            access: self.access & WRITE_ACCESS,
+        };
+        if read_after_write {
+            dependency.stage |= self.write_stage;
+            dependency.access |= self.write_access;
+        }
+
+        self.layout = layout;
+        self.stage = stage;
+        self.access = access;
+
+        if write {
+            self.write_stage = stage;
+            self.write_access = access & WRITE_ACCESS;
+            self.visible_stage = vk::PipelineStageFlags::empty();
+            self.visible_access = vk::AccessFlags::empty();
+        } else {
+            self.visible_stage |= stage;
+            self.visible_access |= access;
+        }
+
+        Some(dependency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_after_write() {
+        let mut state = ResourceState::new(
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        );
+        let transition = |state: &mut ResourceState, access: BufferAccess| {
+            state.transition(
+                vk::ImageLayout::UNDEFINED,
+                access.stage(),
+                access.access(),
+                access.is_write(),
+            )
+        };
+
+        assert_eq!(transition(&mut state, BufferAccess::TransferDst), None);
+        assert_eq!(
+            transition(&mut state, BufferAccess::VertexInput),
+            Some(Dependency {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage: vk::PipelineStageFlags::TRANSFER,
+                access: vk::AccessFlags::TRANSFER_WRITE,
+            })
+        );
+        assert_eq!(transition(&mut state, BufferAccess::VertexInput), None);
+
+        // The write was only made visible to vertex input, uniform reads wait for it again
+        let dependency = transition(&mut state, BufferAccess::Uniform).unwrap();
+        assert!(dependency.stage.contains(vk::PipelineStageFlags::TRANSFER));
+        assert_eq!(dependency.access, vk::AccessFlags::TRANSFER_WRITE);
+        assert_eq!(transition(&mut state, BufferAccess::Uniform), None);
+        assert_eq!(transition(&mut state, BufferAccess::VertexInput), None);
+
+        // A write waits for every read since the last barrier
+        let dependency = transition(&mut state, BufferAccess::StorageWrite).unwrap();
+        assert_eq!(
+            dependency.stage,
+            BufferAccess::Uniform.stage() | vk::PipelineStageFlags::VERTEX_INPUT
+        );
+        assert_eq!(dependency.access, vk::AccessFlags::empty());
+        assert!(transition(&mut state, BufferAccess::Uniform).is_some());
+    }
+}
@@ -0,0 +1,774 @@
+use crate::graphics::command::{CommandEncoder, EncoderError};
+use access::ResourceState;
+use ash::vk;
+use transient::{assign_physical_images, PhysicalImageKey};
+
+pub use access::{BufferAccess, ImageAccess};
+pub use transient::{TransientImageDescription, TransientPool};
+
+pub mod access;
+pub mod transient;
+
+/// Color of the label region each pass is recorded in
+const PASS_LABEL_COLOR: [f32; 4] = [0.4, 0.6, 0.9, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHandle(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferHandle(usize);
+
+/// Image owned outside of the graph, e.g. a swapchain image
+#[derive(Debug, Clone, Copy)]
+pub struct ImportedImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub extent: vk::Extent2D,
+    pub aspect_flags: vk::ImageAspectFlags,
+    pub initial_layout: vk::ImageLayout,
+    /// Stage that produced `initial_layout`, e.g. the semaphore wait stage of an acquired image
+    pub initial_stage: vk::PipelineStageFlags,
+    pub final_access: Option<ImageAccess>,
+}
+
+impl ImportedImage {
+    pub fn new(image: vk::Image, image_view: vk::ImageView, extent: vk::Extent2D) -> Self {
+        Self {
+            image,
+            image_view,
+            extent,
+            aspect_flags: vk::ImageAspectFlags::COLOR,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            initial_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            final_access: None,
+        }
+    }
+
+    pub fn aspect_flags(mut self, aspect_flags: vk::ImageAspectFlags) -> Self {
+        self.aspect_flags = aspect_flags;
+        self
+    }
+
+    pub fn initial_stage(mut self, initial_stage: vk::PipelineStageFlags) -> Self {
+        self.initial_stage = initial_stage;
+        self
+    }
+
+    /// Access the image is transitioned to after the last pass
+    pub fn final_access(mut self, final_access: ImageAccess) -> Self {
+        self.final_access = Some(final_access);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum GraphImage {
+    Imported(ImportedImage),
+    Transient(TransientImageDescription),
+}
+
+impl GraphImage {
+    fn extent(&self) -> vk::Extent2D {
+        match self {
+            GraphImage::Imported(image) => image.extent,
+            GraphImage::Transient(description) => description.extent,
+        }
+    }
+
+    fn aspect_flags(&self) -> vk::ImageAspectFlags {
+        match self {
+            GraphImage::Imported(image) => image.aspect_flags,
+            GraphImage::Transient(description) => description.aspect_flags,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GraphBuffer {
+    buffer: vk::Buffer,
+    final_access: Option<BufferAccess>,
+}
+
+#[derive(Clone, Copy)]
+pub enum LoadOp {
+    Load,
+    Clear(vk::ClearValue),
+    DontCare,
+}
+
+#[derive(Clone, Copy)]
+struct Attachment {
+    image: ImageHandle,
+    load_op: LoadOp,
+}
+
+/// Resources a pass has access to while recording
+pub struct PassContext<'a> {
+    pub encoder: &'a mut CommandEncoder,
+    /// Extent of the first attachment, or zero for passes without attachments
+    pub render_area: vk::Extent2D,
+    images: &'a [(vk::Image, vk::ImageView)],
+    buffers: &'a [GraphBuffer],
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, handle: ImageHandle) -> vk::Image {
+        self.images[handle.0].0
+    }
+
+    pub fn image_view(&self, handle: ImageHandle) -> vk::ImageView {
+        self.images[handle.0].1
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> vk::Buffer {
+        self.buffers[handle.0].buffer
+    }
+}
+
+type RecordFn<'a> = Box<dyn FnOnce(&mut PassContext<'_>) -> Result<(), EncoderError> + 'a>;
+
+struct Pass<'a> {
+    name: String,
+    images: Vec<(ImageHandle, ImageAccess)>,
+    buffers: Vec<(BufferHandle, BufferAccess)>,
+    color_attachments: Vec<Attachment>,
+    depth_attachment: Option<Attachment>,
+    record: Option<RecordFn<'a>>,
+}
+
+/// Barrier computed by [`RenderGraph::compile`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageBarrier {
+    pub image: ImageHandle,
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_access: vk::AccessFlags,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferBarrier {
+    pub buffer: BufferHandle,
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_access: vk::AccessFlags,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Barriers {
+    pub images: Vec<ImageBarrier>,
+    pub buffers: Vec<BufferBarrier>,
+}
+
+impl Barriers {
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty() && self.buffers.is_empty()
+    }
+}
+
+/// Result of [`RenderGraph::compile`]: barriers before each pass and after the last one,
+/// and the physical image every transient image is placed in
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub passes: Vec<Barriers>,
+    pub final_barriers: Barriers,
+    transient_slots: Vec<Option<usize>>,
+    physical_images: Vec<PhysicalImageKey>,
+}
+
+impl Schedule {
+    /// Number of images allocated for transient resources
+    pub fn physical_image_count(&self) -> usize {
+        self.physical_images.len()
+    }
+
+    pub fn physical_image(&self, image: ImageHandle) -> Option<usize> {
+        self.transient_slots[image.0]
+    }
+}
+
+/// Frame graph of passes that declare the resources they read and write.
+/// Passes run in the order they were added; the graph inserts barriers and layout transitions
+/// between them and allocates transient images.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn import_image(&mut self, image: ImportedImage) -> ImageHandle {
+        self.images.push(GraphImage::Imported(image));
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn create_image(&mut self, description: TransientImageDescription) -> ImageHandle {
+        self.images.push(GraphImage::Transient(description));
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// Buffers are never transitioned before the first pass, previous writes must be
+    /// synchronized by the caller
+    pub fn import_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        final_access: Option<BufferAccess>,
+    ) -> BufferHandle {
+        self.buffers.push(GraphBuffer {
+            buffer,
+            final_access,
+        });
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, name: impl Into<String>) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name: name.into(),
+                images: vec![],
+                buffers: vec![],
+                color_attachments: vec![],
+                depth_attachment: None,
+                record: None,
+            },
+        }
+    }
+
+    pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|pass| pass.name.as_str())
+    }
+
+    pub fn compile(&self) -> Schedule {
+        let pass_count = self.passes.len();
+
+        // Lifetimes of transient images
+        let mut lifetimes: Vec<Option<(usize, usize, vk::ImageUsageFlags)>> =
+            vec![None; self.images.len()];
+        for (pass_index, pass) in self.passes.iter().enumerate() {
+            for (image, access) in pass.images.iter() {
+                let lifetime = lifetimes[image.0].get_or_insert((
+                    pass_index,
+                    pass_index,
+                    vk::ImageUsageFlags::empty(),
+                ));
+                lifetime.1 = pass_index;
+                lifetime.2 |= access.usage();
+            }
+        }
+
+        let transients: Vec<_> = self
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| match (image, lifetimes[index]) {
+                (GraphImage::Transient(description), Some((first, last, usage))) => Some((
+                    index,
+                    PhysicalImageKey {
+                        description: *description,
+                        usage,
+                    },
+                    first,
+                    last,
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let (assignment, physical_images) = assign_physical_images(
+            &transients
+                .iter()
+                .map(|(_, key, first, last)| (*key, *first, *last))
+                .collect::<Vec<_>>(),
+        );
+
+        let mut transient_slots = vec![None; self.images.len()];
+        for ((index, ..), slot) in transients.iter().zip(assignment) {
+            transient_slots[*index] = Some(slot);
+        }
+
+        // Imported images track their own state, transients track the state of their slot
+        let state_index = |image: ImageHandle| match transient_slots[image.0] {
+            Some(slot) => self.images.len() + slot,
+            None => image.0,
+        };
+
+        let mut image_states: Vec<_> = self
+            .images
+            .iter()
+            .map(|image| match image {
+                GraphImage::Imported(image) => {
+                    ResourceState::new(image.initial_layout, image.initial_stage)
+                }
+                GraphImage::Transient(_) => ResourceState::new(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                ),
+            })
+            .chain(physical_images.iter().map(|_| {
+                ResourceState::new(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                )
+            }))
+            .collect();
+
+        let mut buffer_states = vec![
+            ResourceState::new(
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::TOP_OF_PIPE
+            );
+            self.buffers.len()
+        ];
+
+        let mut first_use = vec![true; self.images.len()];
+
+        let mut image_barrier = |image: ImageHandle, access: ImageAccess| {
+            let state = &mut image_states[state_index(image)];
+
+            // The contents of a transient image are undefined before its first use
+            if std::mem::take(&mut first_use[image.0]) && transient_slots[image.0].is_some() {
+                state.layout = vk::ImageLayout::UNDEFINED;
+            }
+
+            state
+                .transition(
+                    access.layout(),
+                    access.stage(),
+                    access.access(),
+                    access.is_write(),
+                )
+                .map(|dependency| ImageBarrier {
+                    image,
+                    src_stage: dependency.stage,
+                    dst_stage: access.stage(),
+                    src_access: dependency.access,
+                    dst_access: access.access(),
+                    old_layout: dependency.layout,
+                    new_layout: access.layout(),
+                })
+        };
+
+        let mut buffer_barrier = |buffer: BufferHandle, access: BufferAccess| {
+            buffer_states[buffer.0]
+                .transition(
+                    vk::ImageLayout::UNDEFINED,
+                    access.stage(),
+                    access.access(),
+                    access.is_write(),
+                )
+                .map(|dependency| BufferBarrier {
+                    buffer,
+                    src_stage: dependency.stage,
+                    dst_stage: access.stage(),
+                    src_access: dependency.access,
+                    dst_access: access.access(),
+                })
+        };
+
+        let mut passes = Vec::with_capacity(pass_count);
+        for pass in self.passes.iter() {
+            passes.push(Barriers {
+                images: pass
+                    .images
+                    .iter()
+                    .filter_map(|(image, access)| image_barrier(*image, *access))
+                    .collect(),
+                buffers: pass
+                    .buffers
+                    .iter()
+                    .filter_map(|(buffer, access)| buffer_barrier(*buffer, *access))
+                    .collect(),
+            });
+        }
+
+        let final_barriers = Barriers {
+            images: self
+                .images
+                .iter()
+                .enumerate()
+                .filter_map(|(index, image)| match image {
+                    GraphImage::Imported(ImportedImage {
+                        final_access: Some(access),
+                        ..
+                    }) => image_barrier(ImageHandle(index), *access),
+                    _ => None,
+                })
+                .collect(),
+            buffers: self
+                .buffers
+                .iter()
+                .enumerate()
+                .filter_map(|(index, buffer)| {
+                    buffer
+                        .final_access
+                        .and_then(|access| buffer_barrier(BufferHandle(index), access))
+                })
+                .collect(),
+        };
+
+        Schedule {
+            passes,
+            final_barriers,
+            transient_slots,
+            physical_images,
+        }
+    }
+
+    /// Records all passes into `encoder`. Transient images come from `transient_pool`.
+    pub fn execute(
+        mut self,
+        encoder: &mut CommandEncoder,
+        transient_pool: &mut TransientPool,
+    ) -> Result<(), EncoderError> {
+        let schedule = self.compile();
+
+        transient_pool.retain(&schedule.physical_images);
+        let physical = transient_pool.acquire(encoder.device(), &schedule.physical_images)?;
+
+        let images: Vec<_> = self
+            .images
+            .iter()
+            .enumerate()
+            .map(
+                |(index, image)| match (image, schedule.transient_slots[index]) {
+                    (GraphImage::Imported(image), _) => (image.image, image.image_view),
+                    (GraphImage::Transient(_), Some(slot)) => {
+                        (physical[slot].image(), physical[slot].image_view())
+                    }
+                    // Transient images that no pass uses are never allocated
+                    (GraphImage::Transient(_), None) => (vk::Image::null(), vk::ImageView::null()),
+                },
+            )
+            .collect();
+
+        let passes = std::mem::take(&mut self.passes);
+        for (mut pass, barriers) in passes.into_iter().zip(schedule.passes.iter()) {
+            let name = std::mem::take(&mut pass.name);
+
+            encoder.label(&name, PASS_LABEL_COLOR, |encoder| {
+                self.record_barriers(encoder, barriers, &images)?;
+
+                let render_area = pass
+                    .color_attachments
+                    .iter()
+                    .chain(pass.depth_attachment.iter())
+                    .next()
+                    .map(|attachment| self.images[attachment.image.0].extent())
+                    .unwrap_or_default();
+
+                let rendering =
+                    !pass.color_attachments.is_empty() || pass.depth_attachment.is_some();
+                if rendering {
+                    begin_rendering(encoder, &pass, render_area, &images)?;
+                }
+
+                if let Some(record) = pass.record.take() {
+                    let mut context = PassContext {
+                        encoder,
+                        render_area,
+                        images: &images,
+                        buffers: &self.buffers,
+                    };
+
+                    record(&mut context)?;
+                }
+
+                if rendering {
+                    encoder.end_rendering()?;
+                }
+
+                Ok(())
+            })?;
+        }
+
+        self.record_barriers(encoder, &schedule.final_barriers, &images)
+    }
+
+    fn record_barriers(
+        &self,
+        encoder: &mut CommandEncoder,
+        barriers: &Barriers,
+        images: &[(vk::Image, vk::ImageView)],
+    ) -> Result<(), EncoderError> {
+        if barriers.is_empty() {
+            return Ok(());
+        }
+
+        let mut src_stage = vk::PipelineStageFlags::empty();
+        let mut dst_stage = vk::PipelineStageFlags::empty();
+
+        let image_barriers: Vec<_> = barriers
+            .images
+            .iter()
+            .map(|barrier| {
+                src_stage |= barrier.src_stage;
+                dst_stage |= barrier.dst_stage;
+
+                vk::ImageMemoryBarrier::default()
+                    .image(images[barrier.image.0].0)
+                    .src_access_mask(barrier.src_access)
+                    .dst_access_mask(barrier.dst_access)
+                    .old_layout(barrier.old_layout)
+                    .new_layout(barrier.new_layout)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: self.images[barrier.image.0].aspect_flags(),
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    })
+            })
+            .collect();
+
+        let buffer_barriers: Vec<_> = barriers
+            .buffers
+            .iter()
+            .map(|barrier| {
+                src_stage |= barrier.src_stage;
+                dst_stage |= barrier.dst_stage;
+
+                vk::BufferMemoryBarrier::default()
+                    .buffer(self.buffers[barrier.buffer.0].buffer)
+                    .src_access_mask(barrier.src_access)
+                    .dst_access_mask(barrier.dst_access)
+                    .size(vk::WHOLE_SIZE)
+            })
+            .collect();
+
+        encoder.pipeline_barrier(src_stage, dst_stage, &buffer_barriers, &image_barriers)
+    }
+}
+
+fn begin_rendering(
+    encoder: &mut CommandEncoder,
+    pass: &Pass<'_>,
+    render_area: vk::Extent2D,
+    images: &[(vk::Image, vk::ImageView)],
+) -> Result<(), EncoderError> {
+    let attachment_info = |attachment: &Attachment, layout: vk::ImageLayout| {
+        let info = vk::RenderingAttachmentInfo::default()
+            .image_view(images[attachment.image.0].1)
+            .image_layout(layout)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        match attachment.load_op {
+            LoadOp::Load => info.load_op(vk::AttachmentLoadOp::LOAD),
+            LoadOp::Clear(clear_value) => info
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .clear_value(clear_value),
+            LoadOp::DontCare => info.load_op(vk::AttachmentLoadOp::DONT_CARE),
+        }
+    };
+
+    let color_attachments: Vec<_> = pass
+        .color_attachments
+        .iter()
+        .map(|attachment| attachment_info(attachment, ImageAccess::ColorAttachment.layout()))
+        .collect();
+
+    let depth_attachment = pass
+        .depth_attachment
+        .as_ref()
+        .map(|attachment| attachment_info(attachment, ImageAccess::DepthAttachment.layout()));
+
+    let mut rendering_info = vk::RenderingInfo::default()
+        .render_area(vk::Rect2D::default().extent(render_area))
+        .layer_count(1)
+        .color_attachments(&color_attachments);
+
+    if let Some(depth_attachment) = depth_attachment.as_ref() {
+        rendering_info = rendering_info.depth_attachment(depth_attachment);
+    }
+
+    encoder.begin_rendering(&rendering_info)
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    pass: Pass<'a>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn color_attachment(mut self, image: ImageHandle, load_op: LoadOp) -> Self {
+        self.pass
+            .color_attachments
+            .push(Attachment { image, load_op });
+        self.image(image, ImageAccess::ColorAttachment)
+    }
+
+    pub fn depth_attachment(mut self, image: ImageHandle, load_op: LoadOp) -> Self {
+        self.pass.depth_attachment = Some(Attachment { image, load_op });
+        self.image(image, ImageAccess::DepthAttachment)
+    }
+
+    pub fn image(mut self, image: ImageHandle, access: ImageAccess) -> Self {
+        self.pass.images.push((image, access));
+        self
+    }
+
+    pub fn buffer(mut self, buffer: BufferHandle, access: BufferAccess) -> Self {
+        self.pass.buffers.push((buffer, access));
+        self
+    }
+
+    /// Adds the pass to the graph. Passes with attachments are recorded inside
+    /// `vkCmdBeginRendering`/`vkCmdEndRendering`.
+    pub fn record(
+        mut self,
+        record: impl FnOnce(&mut PassContext<'_>) -> Result<(), EncoderError> + 'a,
+    ) {
+        self.pass.record = Some(Box::new(record));
+        self.graph.passes.push(self.pass);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D {
+        width: 64,
+        height: 64,
+    };
+
+    #[test]
+    fn test_compile_barriers() {
+        let mut graph = RenderGraph::new();
+
+        let swapchain = graph.import_image(
+            ImportedImage::new(vk::Image::null(), vk::ImageView::null(), EXTENT)
+                .final_access(ImageAccess::Present),
+        );
+        let scene = graph.create_image(TransientImageDescription::color(
+            vk::Format::R8G8B8A8_UNORM,
+            EXTENT,
+        ));
+
+        graph
+            .add_pass("scene")
+            .color_attachment(scene, LoadOp::DontCare)
+            .record(|_| Ok(()));
+        graph
+            .add_pass("post")
+            .image(scene, ImageAccess::SampledFragment)
+            .color_attachment(swapchain, LoadOp::DontCare)
+            .record(|_| Ok(()));
+
+        let schedule = graph.compile();
+
+        let layouts = |barriers: &Barriers| -> Vec<_> {
+            barriers
+                .images
+                .iter()
+                .map(|barrier| (barrier.image, barrier.old_layout, barrier.new_layout))
+                .collect()
+        };
+
+        assert_eq!(
+            layouts(&schedule.passes[0]),
+            vec![(
+                scene,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            )]
+        );
+        assert_eq!(
+            layouts(&schedule.passes[1]),
+            vec![
+                (
+                    scene,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                ),
+                (
+                    swapchain,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+                ),
+            ]
+        );
+        assert_eq!(
+            schedule.passes[1].images[0].src_access,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(
+            layouts(&schedule.final_barriers),
+            vec![(
+                swapchain,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageLayout::PRESENT_SRC_KHR
+            )]
+        );
+    }
+
+    #[test]
+    fn test_transient_aliasing_and_buffer_hazards() {
+        let mut graph = RenderGraph::new();
+
+        let description = TransientImageDescription::color(vk::Format::R8G8B8A8_UNORM, EXTENT);
+        let first = graph.create_image(description);
+        let second = graph.create_image(description);
+        let third = graph.create_image(description);
+        let buffer = graph.import_buffer(vk::Buffer::null(), Some(BufferAccess::HostRead));
+
+        graph
+            .add_pass("a")
+            .image(first, ImageAccess::TransferDst)
+            .record(|_| Ok(()));
+        graph
+            .add_pass("b")
+            .image(first, ImageAccess::TransferSrc)
+            .image(second, ImageAccess::TransferDst)
+            .record(|_| Ok(()));
+        graph
+            .add_pass("c")
+            .image(second, ImageAccess::TransferSrc)
+            .image(third, ImageAccess::TransferDst)
+            .buffer(buffer, BufferAccess::TransferDst)
+            .record(|_| Ok(()));
+
+        let schedule = graph.compile();
+
+        // `first` is dead before `third` is written, so they share an image
+        assert_eq!(schedule.physical_image_count(), 2);
+        assert_eq!(
+            schedule.physical_image(first),
+            schedule.physical_image(third)
+        );
+        assert_ne!(
+            schedule.physical_image(first),
+            schedule.physical_image(second)
+        );
+
+        // Writing `third` waits for the transfer reading `first`
+        let third_barrier = schedule.passes[2]
+            .images
+            .iter()
+            .find(|barrier| barrier.image == third)
+            .unwrap();
+        assert_eq!(third_barrier.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(third_barrier.src_stage, vk::PipelineStageFlags::TRANSFER);
+
+        // The first write to the buffer needs no barrier, the host read after it does
+        assert!(schedule.passes[2].buffers.is_empty());
+        assert_eq!(
+            schedule.final_barriers.buffers,
+            vec![BufferBarrier {
+                buffer,
+                src_stage: vk::PipelineStageFlags::TRANSFER,
+                dst_stage: vk::PipelineStageFlags::HOST,
+                src_access: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access: vk::AccessFlags::HOST_READ,
+            }]
+        );
+    }
+}
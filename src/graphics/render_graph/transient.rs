@@ -0,0 +1,137 @@
+use crate::graphics::device::Device;
+use crate::graphics::texture::{Image, ImageDescription};
+use ash::prelude::VkResult;
+use ash::vk;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Image created by the graph, valid only between its first and last use in one frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransientImageDescription {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub aspect_flags: vk::ImageAspectFlags,
+}
+
+impl TransientImageDescription {
+    pub fn color(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self {
+            format,
+            extent,
+            aspect_flags: vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    pub fn depth(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self {
+            format,
+            extent,
+            aspect_flags: vk::ImageAspectFlags::DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct PhysicalImageKey {
+    pub description: TransientImageDescription,
+    pub usage: vk::ImageUsageFlags,
+}
+
+/// Assigns physical images to transient images.
+/// Transients with the same description whose lifetimes `(first pass, last pass)`
+/// don't overlap share one physical image, created with the union of their usages.
+pub(super) fn assign_physical_images(
+    transients: &[(PhysicalImageKey, usize, usize)],
+) -> (Vec<usize>, Vec<PhysicalImageKey>) {
+    let mut order: Vec<_> = (0..transients.len()).collect();
+    order.sort_by_key(|index| transients[*index].1);
+
+    let mut physical: Vec<(PhysicalImageKey, usize)> = vec![];
+    let mut assignment = vec![0; transients.len()];
+
+    for index in order {
+        let (key, first, last) = transients[index];
+
+        let slot = physical.iter().position(|(slot_key, slot_last)| {
+            slot_key.description == key.description && *slot_last < first
+        });
+
+        let slot = match slot {
+            Some(slot) => {
+                physical[slot].0.usage |= key.usage;
+                physical[slot].1 = last;
+                slot
+            }
+            None => {
+                physical.push((key, last));
+                physical.len() - 1
+            }
+        };
+
+        assignment[index] = slot;
+    }
+
+    (
+        assignment,
+        physical.into_iter().map(|(key, _)| key).collect(),
+    )
+}
+
+/// Images backing transient graph resources, reused from frame to frame.
+/// Keep one pool per frame in flight, the images are in use until that frame has finished.
+#[derive(Debug, Default)]
+pub struct TransientPool {
+    images: HashMap<PhysicalImageKey, Vec<Image>>,
+}
+
+impl TransientPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an image for each key, creating the missing ones
+    pub(super) fn acquire(
+        &mut self,
+        device: &Arc<Device>,
+        keys: &[PhysicalImageKey],
+    ) -> VkResult<Vec<&Image>> {
+        let mut counts: HashMap<PhysicalImageKey, usize> = HashMap::new();
+        for key in keys.iter() {
+            *counts.entry(*key).or_default() += 1;
+        }
+
+        for (key, count) in counts.iter() {
+            let images = self.images.entry(*key).or_default();
+
+            while images.len() < *count {
+                let description = ImageDescription::image2d()
+                    .extent(vk::Extent3D {
+                        width: key.description.extent.width,
+                        height: key.description.extent.height,
+                        depth: 1,
+                    })
+                    .format(key.description.format)
+                    .aspect_flags(key.description.aspect_flags)
+                    .usage(key.usage);
+
+                images.push(Image::new(device.clone(), &description)?);
+            }
+        }
+
+        let mut used: HashMap<PhysicalImageKey, usize> = HashMap::new();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let index = used.entry(*key).or_default();
+                let image = &self.images[key][*index];
+                *index += 1;
+                image
+            })
+            .collect())
+    }
+
+    /// Drops images not requested by the last graph, e.g. after a resize
+    pub(super) fn retain(&mut self, keys: &[PhysicalImageKey]) {
+        self.images.retain(|key, _| keys.contains(key));
+    }
+}
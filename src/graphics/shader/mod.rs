@@ -0,0 +1,308 @@
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
+use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use crate::{gfx_debug_exec, gfx_debug_log};
+use ash::prelude::VkResult;
+use ash::vk;
+use reflection::ShaderReflection;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub mod reflection;
+#[cfg(feature = "shader_hot_reload")]
+pub mod watcher;
+
+pub const SHADER_DIR: &str = "res/shaders";
+
+/// Directory with GLSL sources. Falls back to the crate directory so that
+/// `cargo run` and `cargo test` work from anywhere inside the workspace.
+pub fn shader_dir() -> PathBuf {
+    let relative = PathBuf::from(SHADER_DIR);
+
+    if relative.is_dir() {
+        relative
+    } else {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(SHADER_DIR)
+    }
+}
+
+pub fn shader_path(name: &str) -> PathBuf {
+    shader_dir().join(name)
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    UnknownStage {
+        path: PathBuf,
+    },
+    Compile {
+        path: PathBuf,
+        line: u32,
+        column: u32,
+        message: String,
+    },
+    Output {
+        path: PathBuf,
+        message: String,
+    },
+    ModuleCreate(vk::Result),
+}
+
+impl Display for ShaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            ShaderError::UnknownStage { path } => write!(
+                f,
+                "{}: unknown shader stage, expected *.vert.glsl, *.frag.glsl or *.comp.glsl",
+                path.display()
+            ),
+            ShaderError::Compile {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{line}:{column}: {message}", path.display()),
+            ShaderError::Output { path, message } => write!(f, "{}: {message}", path.display()),
+            ShaderError::ModuleCreate(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for ShaderError {}
+
+/// SPIR-V produced from a GLSL source file
+#[derive(Debug, Clone)]
+pub struct CompiledShader {
+    path: PathBuf,
+    stage: vk::ShaderStageFlags,
+    spirv: Vec<u32>,
+    reflection: ShaderReflection,
+}
+
+impl CompiledShader {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn stage(&self) -> vk::ShaderStageFlags {
+        self.stage
+    }
+
+    pub fn spirv(&self) -> &[u32] {
+        &self.spirv
+    }
+
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+}
+
+pub fn stage_from_path(path: &Path) -> Option<naga::ShaderStage> {
+    let file_name = path.file_name()?.to_str()?;
+    let stem = file_name.strip_suffix(".glsl")?;
+
+    match Path::new(stem).extension()?.to_str()? {
+        "vert" => Some(naga::ShaderStage::Vertex),
+        "frag" => Some(naga::ShaderStage::Fragment),
+        "comp" => Some(naga::ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+pub fn compile_glsl_file(path: impl AsRef<Path>) -> Result<CompiledShader, ShaderError> {
+    let path = path.as_ref();
+
+    let source = std::fs::read_to_string(path).map_err(|error| ShaderError::Io {
+        path: path.to_owned(),
+        error,
+    })?;
+
+    let stage = stage_from_path(path).ok_or_else(|| ShaderError::UnknownStage {
+        path: path.to_owned(),
+    })?;
+
+    compile_glsl(path, &source, stage)
+}
+
+/// Compiles GLSL `source` to SPIR-V. `path` is only used for error messages.
+pub fn compile_glsl(
+    path: &Path,
+    source: &str,
+    stage: naga::ShaderStage,
+) -> Result<CompiledShader, ShaderError> {
+    let compile_error = |span: Option<naga::SourceLocation>, message: String| {
+        let (line, column) = span
+            .map(|location| (location.line_number, location.line_position))
+            .unwrap_or((0, 0));
+
+        ShaderError::Compile {
+            path: path.to_owned(),
+            line,
+            column,
+            message,
+        }
+    };
+
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&naga::front::glsl::Options::from(stage), source)
+        .map_err(|errors| {
+            let error = &errors.errors[0];
+            compile_error(error.location(source), error.kind.to_string())
+        })?;
+
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|error| compile_error(error.location(source), error.as_inner().to_string()))?;
+
+    let options = naga::back::spv::Options {
+        flags: naga::back::spv::WriterFlags::LABEL_VARYINGS,
+        ..Default::default()
+    };
+
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage: stage,
+        entry_point: "main".to_owned(),
+    };
+
+    let spirv = naga::back::spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+        .map_err(|error| ShaderError::Output {
+            path: path.to_owned(),
+            message: error.to_string(),
+        })?;
+
+    let stage = match stage {
+        naga::ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
+        naga::ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        _ => vk::ShaderStageFlags::COMPUTE,
+    };
+
+    Ok(CompiledShader {
+        path: path.to_owned(),
+        stage,
+        spirv,
+        reflection: ShaderReflection::from_module(&module, &info, stage),
+    })
+}
+
+#[derive(Debug)]
+pub struct ShaderModule {
+    handle: vk::ShaderModule,
+    stage: vk::ShaderStageFlags,
+    reflection: ShaderReflection,
+    device: Arc<Device>,
+}
+
+impl ShaderModule {
+    pub fn new(device: Arc<Device>, shader: &CompiledShader) -> VkResult<Self> {
+        let create_info = vk::ShaderModuleCreateInfo::default().code(shader.spirv());
+
+        let handle = device.create(&create_info)?;
+
+        Ok(Self {
+            handle,
+            stage: shader.stage(),
+            reflection: shader.reflection().clone(),
+            device,
+        })
+    }
+
+    /// Loads and compiles `name` from [`shader_dir`]
+    pub fn from_file(device: Arc<Device>, name: &str) -> Result<Self, ShaderError> {
+        let shader = compile_glsl_file(shader_path(name))?;
+
+        let module = Self::new(device, &shader).map_err(ShaderError::ModuleCreate)?;
+        gfx_debug_exec!(module.set_object_name(name));
+
+        Ok(module)
+    }
+
+    pub fn handle(&self) -> vk::ShaderModule {
+        self.handle
+    }
+
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+
+    pub fn stage(&self) -> vk::ShaderStageFlags {
+        self.stage
+    }
+
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+}
+
+impl Drop for ShaderModule {
+    fn drop(&mut self) {
+        gfx_debug_log!(stringify!(ShaderModule::drop()));
+        self.device.destroy(self.handle);
+    }
+}
+
+impl DeviceCreateExtend<vk::ShaderModuleCreateInfo<'_>, vk::ShaderModule> for Device {
+    fn create(&self, create_info: &vk::ShaderModuleCreateInfo<'_>) -> VkResult<vk::ShaderModule> {
+        unsafe { self.handle().create_shader_module(create_info, None) }
+    }
+}
+
+impl DeviceDestroyExtend<vk::ShaderModule> for Device {
+    fn destroy(&self, vk_struct: vk::ShaderModule) {
+        unsafe {
+            self.handle().destroy_shader_module(vk_struct, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_default_shaders() {
+        let vertex = compile_glsl_file(shader_path("default.vert.glsl")).unwrap();
+        let fragment = compile_glsl_file(shader_path("default.frag.glsl")).unwrap();
+
+        assert_eq!(vertex.stage(), vk::ShaderStageFlags::VERTEX);
+        assert_eq!(fragment.stage(), vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(vertex.spirv()[0], 0x0723_0203);
+    }
+
+    #[test]
+    fn test_compile_error_location() {
+        let source = "#version 450\n\nvoid main() {\n    undefined_call();\n}\n";
+
+        let error = compile_glsl(
+            Path::new("broken.frag.glsl"),
+            source,
+            naga::ShaderStage::Fragment,
+        )
+        .unwrap_err();
+
+        match error {
+            ShaderError::Compile { path, line, .. } => {
+                assert_eq!(path, Path::new("broken.frag.glsl"));
+                assert_eq!(line, 4);
+            }
+            other => panic!("unexpected error {other}"),
+        }
+    }
+
+    #[test]
+    fn test_stage_from_path() {
+        assert_eq!(
+            stage_from_path(Path::new("res/shaders/default.vert.glsl")),
+            Some(naga::ShaderStage::Vertex)
+        );
+        assert_eq!(stage_from_path(Path::new("default.glsl")), None);
+    }
+}
@@ -0,0 +1,649 @@
+//! Shader interfaces reflected from the naga IR of the GLSL front end, before SPIR-V is
+//! emitted. Precompiled `.spv` modules have no IR and can't be reflected.
+
+use ash::vk;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Vertex shader input at `location`. `format` is the 32-bit format matching the GLSL type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+impl DescriptorBinding {
+    pub fn to_vk(self) -> vk::DescriptorSetLayoutBinding<'static> {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(self.binding)
+            .descriptor_type(self.descriptor_type)
+            .descriptor_count(self.count)
+            .stage_flags(self.stage_flags)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReflectionError {
+    MissingVertexInput {
+        location: u32,
+        format: vk::Format,
+    },
+    VertexFormatMismatch {
+        location: u32,
+        shader: vk::Format,
+        buffer: vk::Format,
+    },
+    PushConstantSize {
+        stage_flags: vk::ShaderStageFlags,
+        shader: u32,
+        layout: u32,
+    },
+    BindingMismatch {
+        set: u32,
+        binding: u32,
+        first: vk::DescriptorType,
+        second: vk::DescriptorType,
+    },
+}
+
+impl Display for ReflectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectionError::MissingVertexInput { location, format } => write!(
+                f,
+                "vertex shader input at location {location} ({format:?}) has no vertex attribute"
+            ),
+            ReflectionError::VertexFormatMismatch {
+                location,
+                shader,
+                buffer,
+            } => write!(
+                f,
+                "vertex attribute at location {location} is {buffer:?}, shader expects {shader:?}"
+            ),
+            ReflectionError::PushConstantSize {
+                stage_flags,
+                shader,
+                layout,
+            } => write!(
+                f,
+                "push constant block of {stage_flags:?} is {shader} bytes, layout covers {layout}"
+            ),
+            ReflectionError::BindingMismatch {
+                set,
+                binding,
+                first,
+                second,
+            } => write!(
+                f,
+                "descriptor set {set} binding {binding} is declared as both {first:?} and {second:?}"
+            ),
+        }
+    }
+}
+
+impl Error for ReflectionError {}
+
+/// Interface of a compiled shader: vertex inputs, push constant block and descriptor bindings.
+/// Reflections of several stages are combined with [`ShaderReflection::merge`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderReflection {
+    stage_flags: vk::ShaderStageFlags,
+    vertex_inputs: Vec<VertexInput>,
+    push_constant_size: Option<u32>,
+    /// Stages that declare the push constant block, a subset of `stage_flags`
+    push_constant_stages: vk::ShaderStageFlags,
+    bindings: Vec<DescriptorBinding>,
+}
+
+impl ShaderReflection {
+    /// Reflects the `main` entry point of a validated module
+    pub fn from_module(
+        module: &naga::Module,
+        info: &naga::valid::ModuleInfo,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        let Some((index, entry_point)) = module
+            .entry_points
+            .iter()
+            .enumerate()
+            .find(|(_, entry_point)| entry_point.name == "main")
+        else {
+            return Self {
+                stage_flags,
+                ..Self::default()
+            };
+        };
+
+        let function_info = info.get_entry_point(index);
+        let ctx = module.to_ctx();
+
+        let mut vertex_inputs = vec![];
+        if entry_point.stage == naga::ShaderStage::Vertex {
+            for argument in entry_point.function.arguments.iter() {
+                collect_vertex_inputs(
+                    module,
+                    argument.ty,
+                    argument.binding.as_ref(),
+                    &mut vertex_inputs,
+                );
+            }
+            vertex_inputs.sort_by_key(|input| input.location);
+        }
+
+        let mut push_constant_size = None;
+        let mut bindings = vec![];
+
+        for (handle, global) in module.global_variables.iter() {
+            if function_info[handle].is_empty() {
+                continue;
+            }
+
+            if global.space == naga::AddressSpace::PushConstant {
+                push_constant_size = Some(module.types[global.ty].inner.size(ctx));
+                continue;
+            }
+
+            let Some(resource) = global.binding.as_ref() else {
+                continue;
+            };
+
+            let (ty, count) = match module.types[global.ty].inner {
+                naga::TypeInner::BindingArray { base, size } => {
+                    let count = match size {
+                        naga::ArraySize::Constant(count) => count.get(),
+                        _ => 1,
+                    };
+                    (base, count)
+                }
+                _ => (global.ty, 1),
+            };
+
+            let Some(descriptor_type) = descriptor_type(global.space, &module.types[ty].inner)
+            else {
+                continue;
+            };
+
+            bindings.push(DescriptorBinding {
+                set: resource.group,
+                binding: resource.binding,
+                descriptor_type,
+                count,
+                stage_flags,
+            });
+        }
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        let push_constant_stages = match push_constant_size {
+            Some(_) => stage_flags,
+            None => vk::ShaderStageFlags::empty(),
+        };
+
+        Self {
+            stage_flags,
+            vertex_inputs,
+            push_constant_size,
+            push_constant_stages,
+            bindings,
+        }
+    }
+
+    /// Combines the reflections of all stages of one pipeline
+    pub fn merge<'a>(
+        reflections: impl IntoIterator<Item = &'a ShaderReflection>,
+    ) -> Result<Self, ReflectionError> {
+        let mut result = Self::default();
+
+        for reflection in reflections {
+            result.stage_flags |= reflection.stage_flags;
+
+            if reflection
+                .stage_flags
+                .contains(vk::ShaderStageFlags::VERTEX)
+            {
+                result.vertex_inputs = reflection.vertex_inputs.clone();
+            }
+
+            result.push_constant_size =
+                match (result.push_constant_size, reflection.push_constant_size) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+            result.push_constant_stages |= reflection.push_constant_stages;
+
+            for binding in reflection.bindings.iter() {
+                let existing = result
+                    .bindings
+                    .iter_mut()
+                    .find(|t| t.set == binding.set && t.binding == binding.binding);
+
+                match existing {
+                    Some(existing) if existing.descriptor_type != binding.descriptor_type => {
+                        return Err(ReflectionError::BindingMismatch {
+                            set: binding.set,
+                            binding: binding.binding,
+                            first: existing.descriptor_type,
+                            second: binding.descriptor_type,
+                        });
+                    }
+                    Some(existing) => {
+                        existing.stage_flags |= binding.stage_flags;
+                        existing.count = existing.count.max(binding.count);
+                    }
+                    None => result.bindings.push(*binding),
+                }
+            }
+        }
+        result
+            .bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(result)
+    }
+
+    pub fn stage_flags(&self) -> vk::ShaderStageFlags {
+        self.stage_flags
+    }
+
+    pub fn vertex_inputs(&self) -> &[VertexInput] {
+        &self.vertex_inputs
+    }
+
+    pub fn push_constant_size(&self) -> Option<u32> {
+        self.push_constant_size
+    }
+
+    pub fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        self.push_constant_stages
+    }
+
+    pub fn bindings(&self) -> &[DescriptorBinding] {
+        &self.bindings
+    }
+
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.push_constant_size.map(|size| {
+            vk::PushConstantRange::default()
+                .stage_flags(self.push_constant_stages)
+                .offset(0)
+                .size(size)
+        })
+    }
+
+    /// Bindings grouped by set index. Sets without bindings are empty,
+    /// so the result can be used directly as the pipeline layout's set list.
+    pub fn set_layout_bindings(&self) -> Vec<Vec<vk::DescriptorSetLayoutBinding<'static>>> {
+        let set_count = self
+            .bindings
+            .iter()
+            .map(|binding| binding.set + 1)
+            .max()
+            .unwrap_or(0);
+
+        (0..set_count)
+            .map(|set| {
+                self.bindings
+                    .iter()
+                    .filter(|binding| binding.set == set)
+                    .map(|binding| binding.to_vk())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Checks that every vertex shader input is fed by an attribute of the same numeric type.
+    /// Component counts may differ, Vulkan fills or drops the missing and extra components.
+    pub fn validate_vertex_input(
+        &self,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Result<(), ReflectionError> {
+        for input in self.vertex_inputs.iter() {
+            let attribute = attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or(ReflectionError::MissingVertexInput {
+                    location: input.location,
+                    format: input.format,
+                })?;
+
+            let compatible = match (numeric_type(input.format), numeric_type(attribute.format)) {
+                (Some(shader), Some(buffer)) => shader == buffer,
+                // Formats we know nothing about are left to the validation layers
+                _ => true,
+            };
+
+            if !compatible {
+                return Err(ReflectionError::VertexFormatMismatch {
+                    location: input.location,
+                    shader: input.format,
+                    buffer: attribute.format,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `ranges` cover the push constant block of every stage
+    pub fn validate_push_constants(
+        &self,
+        ranges: &[vk::PushConstantRange],
+    ) -> Result<(), ReflectionError> {
+        let Some(size) = self.push_constant_size else {
+            return Ok(());
+        };
+
+        let covered = ranges
+            .iter()
+            .filter(|range| range.stage_flags.intersects(self.push_constant_stages))
+            .map(|range| range.offset + range.size)
+            .max()
+            .unwrap_or(0);
+
+        if covered < size {
+            return Err(ReflectionError::PushConstantSize {
+                stage_flags: self.push_constant_stages,
+                shader: size,
+                layout: covered,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn collect_vertex_inputs(
+    module: &naga::Module,
+    ty: naga::Handle<naga::Type>,
+    binding: Option<&naga::Binding>,
+    inputs: &mut Vec<VertexInput>,
+) {
+    match (binding, &module.types[ty].inner) {
+        (Some(naga::Binding::Location { location, .. }), inner) => inputs.push(VertexInput {
+            location: *location,
+            format: vertex_format(inner),
+        }),
+        (None, naga::TypeInner::Struct { members, .. }) => {
+            for member in members.iter() {
+                collect_vertex_inputs(module, member.ty, member.binding.as_ref(), inputs);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn vertex_format(inner: &naga::TypeInner) -> vk::Format {
+    let (scalar, components) = match *inner {
+        naga::TypeInner::Scalar(scalar) => (scalar, 1),
+        naga::TypeInner::Vector { size, scalar } => (scalar, size as u32),
+        _ => return vk::Format::UNDEFINED,
+    };
+
+    let formats = match (scalar.kind, scalar.width) {
+        (naga::ScalarKind::Float, 4) => [
+            vk::Format::R32_SFLOAT,
+            vk::Format::R32G32_SFLOAT,
+            vk::Format::R32G32B32_SFLOAT,
+            vk::Format::R32G32B32A32_SFLOAT,
+        ],
+        (naga::ScalarKind::Float, 8) => [
+            vk::Format::R64_SFLOAT,
+            vk::Format::R64G64_SFLOAT,
+            vk::Format::R64G64B64_SFLOAT,
+            vk::Format::R64G64B64A64_SFLOAT,
+        ],
+        (naga::ScalarKind::Sint, 4) => [
+            vk::Format::R32_SINT,
+            vk::Format::R32G32_SINT,
+            vk::Format::R32G32B32_SINT,
+            vk::Format::R32G32B32A32_SINT,
+        ],
+        (naga::ScalarKind::Uint, 4) => [
+            vk::Format::R32_UINT,
+            vk::Format::R32G32_UINT,
+            vk::Format::R32G32B32_UINT,
+            vk::Format::R32G32B32A32_UINT,
+        ],
+        _ => return vk::Format::UNDEFINED,
+    };
+
+    formats[components as usize - 1]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumericType {
+    Float,
+    /// 64-bit floats, they need twice the input locations
+    Double,
+    Sint,
+    Uint,
+}
+
+/// Numeric type the shader sees when reading `format`. The component count isn't part of it,
+/// missing components are filled with defaults and extra ones are dropped.
+fn numeric_type(format: vk::Format) -> Option<NumericType> {
+    use NumericType::*;
+
+    let numeric_type = match format {
+        vk::Format::R64_SFLOAT
+        | vk::Format::R64G64_SFLOAT
+        | vk::Format::R64G64B64_SFLOAT
+        | vk::Format::R64G64B64A64_SFLOAT => Double,
+        vk::Format::R32_SFLOAT
+        | vk::Format::R32G32_SFLOAT
+        | vk::Format::R32G32B32_SFLOAT
+        | vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R16_SFLOAT
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R16G16B16_SFLOAT
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R16_UNORM
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16_SNORM
+        | vk::Format::R16G16_SNORM
+        | vk::Format::R16G16B16A16_SNORM
+        | vk::Format::R8_UNORM
+        | vk::Format::R8G8_UNORM
+        | vk::Format::R8G8B8_UNORM
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8_SNORM
+        | vk::Format::R8G8_SNORM
+        | vk::Format::R8G8B8_SNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::A2B10G10R10_UNORM_PACK32 => Float,
+        vk::Format::R32_SINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32B32_SINT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::R16_SINT
+        | vk::Format::R16G16_SINT
+        | vk::Format::R16G16B16_SINT
+        | vk::Format::R16G16B16A16_SINT
+        | vk::Format::R8_SINT
+        | vk::Format::R8G8_SINT
+        | vk::Format::R8G8B8_SINT
+        | vk::Format::R8G8B8A8_SINT => Sint,
+        vk::Format::R32_UINT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32B32_UINT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R16_UINT
+        | vk::Format::R16G16_UINT
+        | vk::Format::R16G16B16_UINT
+        | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R8_UINT
+        | vk::Format::R8G8_UINT
+        | vk::Format::R8G8B8_UINT
+        | vk::Format::R8G8B8A8_UINT => Uint,
+        _ => return None,
+    };
+
+    Some(numeric_type)
+}
+
+fn descriptor_type(
+    space: naga::AddressSpace,
+    inner: &naga::TypeInner,
+) -> Option<vk::DescriptorType> {
+    let descriptor_type = match (space, inner) {
+        (naga::AddressSpace::Uniform, _) => vk::DescriptorType::UNIFORM_BUFFER,
+        (naga::AddressSpace::Storage { .. }, _) => vk::DescriptorType::STORAGE_BUFFER,
+        (naga::AddressSpace::Handle, naga::TypeInner::Sampler { .. }) => {
+            vk::DescriptorType::SAMPLER
+        }
+        (
+            naga::AddressSpace::Handle,
+            naga::TypeInner::Image {
+                class: naga::ImageClass::Storage { .. },
+                ..
+            },
+        ) => vk::DescriptorType::STORAGE_IMAGE,
+        (naga::AddressSpace::Handle, naga::TypeInner::Image { .. }) => {
+            vk::DescriptorType::SAMPLED_IMAGE
+        }
+        _ => return None,
+    };
+
+    Some(descriptor_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compile_glsl, compile_glsl_file, shader_path};
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_reflect_default_shaders() {
+        let vertex = compile_glsl_file(shader_path("default.vert.glsl")).unwrap();
+        let fragment = compile_glsl_file(shader_path("default.frag.glsl")).unwrap();
+
+        let reflection =
+            ShaderReflection::merge([vertex.reflection(), fragment.reflection()]).unwrap();
+
+        assert_eq!(
+            reflection.vertex_inputs(),
+            &[
+                VertexInput {
+                    location: 0,
+                    format: vk::Format::R32G32B32_SFLOAT
+                },
+                VertexInput {
+                    location: 1,
+                    format: vk::Format::R32G32B32_SFLOAT
+                },
+            ]
+        );
+        assert_eq!(reflection.push_constant_size(), Some(3 * 16 * 4));
+        assert!(reflection.bindings().is_empty());
+    }
+
+    #[test]
+    fn test_push_constant_range_stages() {
+        let vertex = compile_glsl_file(shader_path("default.vert.glsl")).unwrap();
+        let fragment = compile_glsl_file(shader_path("default.frag.glsl")).unwrap();
+        assert_eq!(fragment.reflection().push_constant_size(), None);
+
+        let reflection =
+            ShaderReflection::merge([vertex.reflection(), fragment.reflection()]).unwrap();
+        let range = reflection.push_constant_range().unwrap();
+
+        assert_eq!(
+            reflection.stage_flags(),
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+        assert_eq!(range.stage_flags, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(range.size, 3 * 16 * 4);
+    }
+
+    #[test]
+    fn test_reflect_descriptor_bindings() {
+        let source = "#version 450
+layout(set = 0, binding = 0) uniform Globals { vec4 tint; };
+layout(set = 1, binding = 2) uniform texture2D color_texture;
+layout(set = 1, binding = 3) uniform sampler color_sampler;
+layout(location = 0) out vec4 color;
+void main() {
+    color = tint * texture(sampler2D(color_texture, color_sampler), vec2(0.5));
+}
+";
+        let shader = compile_glsl(
+            Path::new("bindings.frag.glsl"),
+            source,
+            naga::ShaderStage::Fragment,
+        )
+        .unwrap();
+
+        let reflection = shader.reflection();
+        let types: Vec<_> = reflection
+            .bindings()
+            .iter()
+            .map(|binding| (binding.set, binding.binding, binding.descriptor_type))
+            .collect();
+
+        assert_eq!(
+            types,
+            vec![
+                (0, 0, vk::DescriptorType::UNIFORM_BUFFER),
+                (1, 2, vk::DescriptorType::SAMPLED_IMAGE),
+                (1, 3, vk::DescriptorType::SAMPLER),
+            ]
+        );
+        assert_eq!(reflection.set_layout_bindings().len(), 2);
+    }
+
+    #[test]
+    fn test_validate_vertex_input() {
+        let vertex = compile_glsl_file(shader_path("default.vert.glsl")).unwrap();
+        let reflection = vertex.reflection();
+
+        let attribute = |location, format| {
+            vk::VertexInputAttributeDescription::default()
+                .location(location)
+                .format(format)
+        };
+
+        reflection
+            .validate_vertex_input(&[
+                attribute(0, vk::Format::R32G32B32_SFLOAT),
+                attribute(1, vk::Format::R8G8B8_UNORM),
+            ])
+            .unwrap();
+        reflection
+            .validate_vertex_input(&[
+                attribute(0, vk::Format::R32G32_SFLOAT),
+                attribute(1, vk::Format::R8G8B8A8_UNORM),
+            ])
+            .unwrap();
+
+        assert!(matches!(
+            reflection.validate_vertex_input(&[attribute(0, vk::Format::R32G32B32_SFLOAT)]),
+            Err(ReflectionError::MissingVertexInput { location: 1, .. })
+        ));
+        assert!(matches!(
+            reflection.validate_vertex_input(&[
+                attribute(0, vk::Format::R32G32B32_SINT),
+                attribute(1, vk::Format::R32G32B32_SFLOAT),
+            ]),
+            Err(ReflectionError::VertexFormatMismatch { location: 0, .. })
+        ));
+        assert!(matches!(
+            reflection.validate_vertex_input(&[
+                attribute(0, vk::Format::R32G32B32_SFLOAT),
+                attribute(1, vk::Format::R64G64B64_SFLOAT),
+            ]),
+            Err(ReflectionError::VertexFormatMismatch { location: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use super::stage_from_path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Polls a shader directory for modified GLSL sources.
+/// Only files with a known stage suffix (see [`stage_from_path`]) are tracked.
+#[derive(Debug)]
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: HashMap<PathBuf, SystemTime>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let modified = scan(&dir);
+
+        Self {
+            dir,
+            modified,
+            interval: POLL_INTERVAL,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns files created or modified since the previous poll.
+    /// Calls made sooner than the poll interval return nothing.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let current = scan(&self.dir);
+        let changed = current
+            .iter()
+            .filter(|(path, modified)| self.modified.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        self.modified = current;
+
+        changed
+    }
+}
+
+fn scan(dir: &Path) -> HashMap<PathBuf, SystemTime> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            stage_from_path(&path)?;
+            let modified = path.metadata().ok()?.modified().ok()?;
+
+            Some((path, modified))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_poll_reports_modified_shader() {
+        let dir = std::env::temp_dir().join(format!("lpps_shader_watcher_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let shader = dir.join("test.frag.glsl");
+        std::fs::write(&shader, "#version 450\nvoid main() {}\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let mut watcher = ShaderWatcher::new(&dir).interval(Duration::ZERO);
+        assert!(watcher.poll().is_empty());
+
+        let modified = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&shader)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert_eq!(watcher.poll(), vec![shader]);
+        assert!(watcher.poll().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
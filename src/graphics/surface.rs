@@ -4,18 +4,18 @@ use crate::{gfx_debug_log, utils};
 use ash::prelude::VkResult;
 use ash::vk;
 use std::fmt::{Debug, Formatter};
-use std::rc::Rc;
+use std::sync::Arc;
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
 pub struct Surface {
     // swapchain: RwLock<Option<Swapchain>>,
     handle: vk::SurfaceKHR,
     surface_fn: ash::khr::surface::Instance,
-    instance: Rc<Instance>,
+    instance: Arc<Instance>,
 }
 
 impl Surface {
-    pub fn from_window<T>(instance: Rc<Instance>, window_handle: &T) -> VkResult<Self>
+    pub fn from_window<T>(instance: Arc<Instance>, window_handle: &T) -> VkResult<Self>
     where
         T: HasDisplayHandle + HasWindowHandle,
     {
@@ -32,6 +32,21 @@ impl Surface {
         })
     }
 
+    /// Creates a surface backed by `VK_EXT_headless_surface`, which needs no window system.
+    /// The instance must be created with [`utils::gfx::enumerate_headless_extensions`].
+    pub fn headless(instance: Arc<Instance>) -> VkResult<Self> {
+        let handle =
+            unsafe { utils::gfx::create_headless_surface(&instance.entry(), &instance.handle()) }?;
+
+        let surface_fn = ash::khr::surface::Instance::new(&instance.entry(), &instance.handle());
+
+        Ok(Self {
+            handle,
+            surface_fn,
+            instance,
+        })
+    }
+
     pub fn handle(&self) -> vk::SurfaceKHR {
         self.handle
     }
@@ -1,26 +1,31 @@
+use super::debug_utils::DeviceDebugUtilsFns;
 use super::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
 use super::surface::Surface;
 use super::texture::swapchain_image::SwapchainImage;
+use crate::gfx_debug_exec;
 use crate::utils::IntoExtent3D;
 use ash::prelude::VkResult;
 use ash::vk;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Swapchain {
     images: Vec<SwapchainImage>,
     handle: vk::SwapchainKHR,
-    device: Rc<Device>,
+    /// Kept alive until the deferred destroy of the swapchain has run
+    surface: Arc<Surface>,
+    device: Arc<Device>,
 
     image_format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
     present_mode: vk::PresentModeKHR,
     extent: vk::Extent2D,
 }
 
 impl Swapchain {
     pub fn new(
-        device: Rc<Device>,
-        surface: &Surface,
+        device: Arc<Device>,
+        surface: Arc<Surface>,
         description: SwapchainDescription,
     ) -> VkResult<Self> {
         let create_info = {
@@ -49,25 +54,35 @@ impl Swapchain {
         let extent = description.image_description.extent;
         let image_extent = extent.into_extent3d();
 
-        let images: Vec<_> = device
-            .get_swapchain_images(swapchain)
-            .expect("Error while get swapchain images")
-            .into_iter()
-            .map(|image| {
-                SwapchainImage::new(
-                    device.clone(),
-                    image,
-                    description.image_description.format,
-                    image_extent,
-                )
-            })
-            .collect();
+        let images = device.get_swapchain_images(swapchain).and_then(|images| {
+            images
+                .into_iter()
+                .map(|image| {
+                    SwapchainImage::new(
+                        device.clone(),
+                        image,
+                        description.image_description.format,
+                        image_extent,
+                    )
+                })
+                .collect::<VkResult<Vec<_>>>()
+        });
+
+        let images = match images {
+            Ok(images) => images,
+            Err(e) => {
+                destroy_swapchain(&device, swapchain, surface);
+                return Err(e);
+            }
+        };
 
         Ok(Self {
             images,
             handle: swapchain,
+            surface,
             device,
             image_format: description.image_description.format,
+            color_space: description.image_description.color_space,
             present_mode: description.present_mode,
             extent,
         })
@@ -77,15 +92,44 @@ impl Swapchain {
         self.handle
     }
 
+    /// Names the swapchain and its images, which are numbered by image index
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+
+        for (index, image) in self.images.iter().enumerate() {
+            gfx_debug_exec!(image.set_object_name(&format!("{name} image {index}")));
+        }
+    }
+
     pub fn extent(&self) -> vk::Extent2D {
         self.extent
     }
 
+    pub fn image_format(&self) -> vk::Format {
+        self.image_format
+    }
+
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.color_space
+    }
+
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn image(&self, index: u32) -> &SwapchainImage {
+        &self.images[index as usize]
+    }
+
     pub fn get_current_image(
         &self,
         present_semaphore: vk::Semaphore,
         fence: Option<vk::Fence>,
-    ) -> VkResult<(SwapchainImage, u32, bool)> {
+    ) -> VkResult<(&SwapchainImage, u32, bool)> {
         let fence = match fence {
             Some(value) => value,
             None => vk::Fence::null(),
@@ -100,7 +144,7 @@ impl Swapchain {
             )?
         };
 
-        let image = self.images[index as usize].clone();
+        let image = &self.images[index as usize];
 
         Ok((image, index, suboptimal))
     }
@@ -108,27 +152,216 @@ impl Swapchain {
 
 impl Drop for Swapchain {
     fn drop(&mut self) {
-        for image in &self.images {
-            image.destroy();
-        }
-        self.device.destroy(self.handle);
+        // Views have to go before the images they were created from
+        self.images.clear();
+        destroy_swapchain(&self.device, self.handle, self.surface.clone());
     }
 }
 
+/// Defers the destroy of `swapchain` and keeps `surface` alive until it has run
+fn destroy_swapchain(device: &Device, swapchain: vk::SwapchainKHR, surface: Arc<Surface>) {
+    device.defer_destroy(move |device| {
+        device.destroy(swapchain);
+        drop(surface);
+    });
+}
+
 impl DeviceCreateExtend<vk::SwapchainCreateInfoKHR<'_>, vk::SwapchainKHR> for Device {
     fn create(&self, create_info: &vk::SwapchainCreateInfoKHR<'_>) -> VkResult<vk::SwapchainKHR> {
         unsafe { self.swapchain_fns().create_swapchain(create_info, None) }
     }
 }
 
+/// Destroys the swapchain right away, [`Swapchain`] defers it together with its surface
 impl DeviceDestroyExtend<vk::SwapchainKHR> for Device {
     fn destroy(&self, vk_struct: vk::SwapchainKHR) {
-        unsafe {
-            self.swapchain_fns().destroy_swapchain(vk_struct, None);
+        unsafe { self.swapchain_fns().destroy_swapchain(vk_struct, None) };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Vsync {
+    /// `FIFO`, always supported
+    On,
+    /// `FIFO_RELAXED`, tears instead of waiting when a frame is late
+    Relaxed,
+    /// `MAILBOX`, then `IMMEDIATE`
+    #[default]
+    Off,
+}
+
+impl Vsync {
+    /// Present modes in order of preference, `FIFO` is the last resort
+    pub fn present_modes(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            Vsync::On => &[vk::PresentModeKHR::FIFO],
+            Vsync::Relaxed => &[vk::PresentModeKHR::FIFO_RELAXED, vk::PresentModeKHR::FIFO],
+            Vsync::Off => &[
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::FIFO,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageCountPolicy {
+    Minimum,
+    /// One image more than the minimum, so the driver never blocks the renderer
+    #[default]
+    MinimumPlusOne,
+    /// Clamped to the range the surface supports
+    Exact(u32),
+}
+
+/// User preferences for the swapchain, negotiated against what the surface supports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapchainConfig {
+    pub vsync: Vsync,
+    /// Tried in order, the first format the surface supports is used
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub image_count: ImageCountPolicy,
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        let srgb = |format| vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+
+        Self {
+            vsync: Vsync::default(),
+            formats: vec![
+                srgb(vk::Format::B8G8R8A8_SRGB),
+                srgb(vk::Format::R8G8B8A8_SRGB),
+            ],
+            image_count: ImageCountPolicy::default(),
         }
     }
 }
 
+impl SwapchainConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vsync(mut self, vsync: Vsync) -> Self {
+        self.vsync = vsync;
+        self
+    }
+
+    pub fn formats(mut self, formats: Vec<vk::SurfaceFormatKHR>) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    pub fn image_count(mut self, image_count: ImageCountPolicy) -> Self {
+        self.image_count = image_count;
+        self
+    }
+
+    /// First supported mode of the vsync preference. `FIFO` is required by the spec,
+    /// so it is used when nothing else matches.
+    pub fn choose_present_mode(&self, available: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        self.vsync
+            .present_modes()
+            .iter()
+            .copied()
+            .find(|mode| available.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+
+    /// First supported preferred format, then any sRGB format, then whatever comes first.
+    /// Returns `None` only if the surface reports no formats.
+    pub fn choose_surface_format(
+        &self,
+        available: &[vk::SurfaceFormatKHR],
+    ) -> Option<vk::SurfaceFormatKHR> {
+        // A single UNDEFINED entry means the surface takes any format
+        if let [only] = available {
+            if only.format == vk::Format::UNDEFINED {
+                return self.formats.first().copied().or(Some(*only));
+            }
+        }
+
+        let is_srgb = |format: vk::Format| {
+            matches!(
+                format,
+                vk::Format::B8G8R8A8_SRGB
+                    | vk::Format::R8G8B8A8_SRGB
+                    | vk::Format::A8B8G8R8_SRGB_PACK32
+            )
+        };
+
+        self.formats
+            .iter()
+            .find(|preferred| available.contains(preferred))
+            .copied()
+            .or_else(|| {
+                available.iter().copied().find(|format| {
+                    is_srgb(format.format)
+                        && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                })
+            })
+            .or_else(|| available.first().copied())
+    }
+
+    pub fn choose_image_count(&self, capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+        let count = match self.image_count {
+            ImageCountPolicy::Minimum => capabilities.min_image_count,
+            ImageCountPolicy::MinimumPlusOne => capabilities.min_image_count + 1,
+            ImageCountPolicy::Exact(count) => count,
+        };
+
+        // A maximum of zero means there is no limit
+        let max = match capabilities.max_image_count {
+            0 => u32::MAX,
+            max => max,
+        };
+
+        count.clamp(capabilities.min_image_count, max)
+    }
+}
+
+/// `OPAQUE` if supported, otherwise the first mode the surface allows
+pub fn choose_composite_alpha(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+) -> vk::CompositeAlphaFlagsKHR {
+    [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::INHERIT,
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+    ]
+    .into_iter()
+    .find(|mode| capabilities.supported_composite_alpha.contains(*mode))
+    .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+}
+
+/// Extent of the swapchain images. Surfaces that report a current extent must be matched
+/// exactly, the others (e.g. Wayland or headless) take `requested` within the allowed range.
+pub fn choose_extent(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    requested: vk::Extent2D,
+) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        return capabilities.current_extent;
+    }
+
+    vk::Extent2D {
+        width: requested.width.clamp(
+            capabilities.min_image_extent.width,
+            capabilities.max_image_extent.width,
+        ),
+        height: requested.height.clamp(
+            capabilities.min_image_extent.height,
+            capabilities.max_image_extent.height,
+        ),
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct SwapchainImageDescription {
     pub format: vk::Format,
@@ -148,3 +381,108 @@ pub struct SwapchainDescription {
     pub present_mode: vk::PresentModeKHR,
     pub old_swapchain: Option<vk::SwapchainKHR>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiation_fallbacks() {
+        let config = SwapchainConfig::new();
+
+        assert_eq!(
+            config.choose_present_mode(&[vk::PresentModeKHR::FIFO]),
+            vk::PresentModeKHR::FIFO
+        );
+        assert_eq!(
+            config
+                .choose_present_mode(&[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX]),
+            vk::PresentModeKHR::MAILBOX
+        );
+        assert_eq!(
+            config
+                .clone()
+                .vsync(Vsync::Relaxed)
+                .choose_present_mode(&[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO]),
+            vk::PresentModeKHR::FIFO
+        );
+
+        let unorm = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+        let rgba_srgb = vk::SurfaceFormatKHR {
+            format: vk::Format::R8G8B8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+        assert_eq!(
+            config.choose_surface_format(&[unorm, rgba_srgb]),
+            Some(rgba_srgb)
+        );
+        assert_eq!(config.choose_surface_format(&[unorm]), Some(unorm));
+        assert_eq!(config.choose_surface_format(&[]), None);
+
+        let capabilities = vk::SurfaceCapabilitiesKHR {
+            min_image_count: 2,
+            max_image_count: 3,
+            ..Default::default()
+        };
+        assert_eq!(config.choose_image_count(&capabilities), 3);
+        assert_eq!(
+            config
+                .clone()
+                .image_count(ImageCountPolicy::Exact(8))
+                .choose_image_count(&capabilities),
+            3
+        );
+        assert_eq!(
+            config
+                .image_count(ImageCountPolicy::Exact(8))
+                .choose_image_count(&vk::SurfaceCapabilitiesKHR {
+                    max_image_count: 0,
+                    ..capabilities
+                }),
+            8
+        );
+    }
+
+    #[test]
+    fn test_choose_extent() {
+        let requested = vk::Extent2D {
+            width: 800,
+            height: 10,
+        };
+
+        let fixed = vk::SurfaceCapabilitiesKHR {
+            current_extent: vk::Extent2D {
+                width: 640,
+                height: 480,
+            },
+            ..Default::default()
+        };
+        assert_eq!(choose_extent(&fixed, requested), fixed.current_extent);
+
+        let free = vk::SurfaceCapabilitiesKHR {
+            current_extent: vk::Extent2D {
+                width: u32::MAX,
+                height: u32::MAX,
+            },
+            min_image_extent: vk::Extent2D {
+                width: 1,
+                height: 16,
+            },
+            max_image_extent: vk::Extent2D {
+                width: 512,
+                height: 512,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            choose_extent(&free, requested),
+            vk::Extent2D {
+                width: 512,
+                height: 16,
+            }
+        );
+    }
+}
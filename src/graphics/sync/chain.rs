@@ -0,0 +1,114 @@
+use super::{submit_task::SubmitTask, timeline::TimelinePoint, GPUTask, TaskResult};
+use crate::graphics::device::Queue;
+use ash::vk;
+
+/// Submission that runs after `previous`, created by [`GPUTask::then_submit`]
+#[derive(Debug)]
+pub struct ChainTask<T> {
+    previous: T,
+    next: SubmitTask,
+}
+
+impl<T: GPUTask> ChainTask<T> {
+    pub(super) fn new(previous: T, next: SubmitTask) -> Self {
+        Self { previous, next }
+    }
+
+    pub fn previous(&self) -> &T {
+        &self.previous
+    }
+}
+
+impl<T: GPUTask> GPUTask for ChainTask<T> {
+    type Output = ();
+
+    fn wait_result(&self) -> TaskResult<Self::Output> {
+        self.previous.wait()?;
+        self.next.wait_result()
+    }
+
+    fn get_signal_semaphores(&self) -> Vec<vk::Semaphore> {
+        self.next.get_signal_semaphores()
+    }
+
+    /// The next submission waits for the previous one, so its completion implies both
+    fn completion(&self) -> Vec<TimelinePoint> {
+        self.next.completion()
+    }
+
+    fn queue(&self) -> &Queue {
+        self.next.queue()
+    }
+}
+
+/// Task whose following submissions go to another queue,
+/// created by [`GPUTask::then_execute_on`]
+#[derive(Debug)]
+pub struct QueueTask<T> {
+    task: T,
+    queue: Queue,
+}
+
+impl<T: GPUTask> QueueTask<T> {
+    pub(super) fn new(task: T, queue: Queue) -> Self {
+        Self { task, queue }
+    }
+}
+
+impl<T: GPUTask> GPUTask for QueueTask<T> {
+    type Output = T::Output;
+
+    fn wait_result(&self) -> TaskResult<Self::Output> {
+        self.task.wait_result()
+    }
+
+    fn get_signal_semaphores(&self) -> Vec<vk::Semaphore> {
+        self.task.get_signal_semaphores()
+    }
+
+    fn completion(&self) -> Vec<TimelinePoint> {
+        self.task.completion()
+    }
+
+    fn queue(&self) -> &Queue {
+        &self.queue
+    }
+}
+
+/// Task that finishes when both tasks have finished, created by [`GPUTask::join`].
+/// Following submissions go to the queue of the first task.
+#[derive(Debug)]
+pub struct JoinTask<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: GPUTask, B: GPUTask> JoinTask<A, B> {
+    pub(super) fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A: GPUTask, B: GPUTask> GPUTask for JoinTask<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn wait_result(&self) -> TaskResult<Self::Output> {
+        Ok((self.first.wait_result()?, self.second.wait_result()?))
+    }
+
+    fn get_signal_semaphores(&self) -> Vec<vk::Semaphore> {
+        let mut semaphores = self.first.get_signal_semaphores();
+        semaphores.extend(self.second.get_signal_semaphores());
+        semaphores
+    }
+
+    fn completion(&self) -> Vec<TimelinePoint> {
+        let mut points = self.first.completion();
+        points.extend(self.second.completion());
+        points
+    }
+
+    fn queue(&self) -> &Queue {
+        self.first.queue()
+    }
+}
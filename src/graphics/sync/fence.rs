@@ -1,16 +1,18 @@
+use crate::gfx_debug_exec;
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
 use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
 use ash::prelude::VkResult;
 use ash::vk;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Fence {
     handle: vk::Fence,
-    device: Rc<Device>,
+    device: Arc<Device>,
 }
 
 impl Fence {
-    pub fn new(device: Rc<Device>, signaled: bool) -> VkResult<Self> {
+    pub fn new(device: Arc<Device>, signaled: bool) -> VkResult<Self> {
         let mut create_info = vk::FenceCreateInfo::default();
 
         if signaled {
@@ -26,6 +28,10 @@ impl Fence {
         self.handle
     }
 
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+
     pub fn wait(&self, timeout: u64) -> VkResult<()> {
         unsafe {
             self.device
@@ -34,10 +40,8 @@ impl Fence {
         }
     }
 
-    pub fn reset(&self) {
-        self.device
-            .reset(self.handle)
-            .expect("Error while reset fence");
+    pub fn reset(&self) -> VkResult<()> {
+        self.device.reset(self.handle)
     }
 
     pub fn as_shared(&self) -> SharedFence {
@@ -54,7 +58,7 @@ impl Drop for Fence {
 #[derive(Debug, Clone)]
 pub struct SharedFence {
     handle: vk::Fence,
-    device: Rc<Device>,
+    device: Arc<Device>,
 }
 
 impl SharedFence {
@@ -77,10 +81,8 @@ impl SharedFence {
         }
     }
 
-    pub fn reset(&self) {
-        self.device
-            .reset(self.handle)
-            .expect("Error while reset fence");
+    pub fn reset(&self) -> VkResult<()> {
+        self.device.reset(self.handle)
     }
 }
 
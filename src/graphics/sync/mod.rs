@@ -1,10 +1,18 @@
+use ash::prelude::VkResult;
 use ash::vk;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 
-use super::device::Queue;
+use super::device::{Queue, QueueGuard};
+use timeline::TimelinePoint;
 
+pub use chain::{ChainTask, JoinTask, QueueTask};
+
+pub mod chain;
 pub mod fence;
 pub mod semaphore;
 pub mod submit_task;
+pub mod timeline;
 
 pub trait GPUTaskRunner {
     type Output: GPUTask;
@@ -19,11 +27,12 @@ where
     runner.run_task()
 }
 
-pub trait GPUTask {
+/// Work submitted to the GPU. Tasks can be chained with [`GPUTask::then_submit`] and
+/// [`GPUTask::join`], the semaphores between them are wired through queue timelines,
+/// e.g. `upload.then_submit(render)?.then_present(queue, swapchain, image_index)`.
+pub trait GPUTask: Sized {
     type Output;
 
-    fn run(&self, queue: Queue) -> TaskResult<()>;
-
     fn wait(&self) -> TaskResult<()> {
         match self.wait_result() {
             Ok(_) => Ok(()),
@@ -31,64 +40,238 @@ pub trait GPUTask {
         }
     }
 
+    /// Waits until the task and every task it depends on has finished
     fn wait_result(&self) -> TaskResult<Self::Output>;
 
-    fn get_signal_semaphore(&self) -> vk::Semaphore;
+    /// Binary semaphores signaled by the task, presentation waits on all of them
+    fn get_signal_semaphores(&self) -> Vec<vk::Semaphore>;
+
+    /// Timeline values reached once the task has finished
+    fn completion(&self) -> Vec<TimelinePoint>;
 
-    fn then_present(&self, queue: Queue, swapchain: vk::SwapchainKHR, image_index: u32) -> TaskResult<bool> {
-        let wait_semaphore = self.get_signal_semaphore();
+    /// Queue the next task of the chain is submitted to
+    fn queue(&self) -> &Queue;
+
+    fn then_present(
+        &self,
+        queue: Queue,
+        swapchain: vk::SwapchainKHR,
+        image_index: u32,
+    ) -> TaskResult<bool> {
+        let wait_semaphores = self.get_signal_semaphores();
+        if wait_semaphores.is_empty() {
+            return Err(GPUTaskError::NoPresentSemaphore);
+        }
 
         let info = PresentInfo {
             swapchain,
             image_index,
-            wait_semaphore,
+            wait_semaphores,
         };
 
-        queue.present(info.to_vk())
-            .map_err(|_| GPUTaskError::PresentError)
+        queue
+            .lock()
+            .present(info.to_vk())
+            .map_err(GPUTaskError::Present)
+    }
+
+    /// Submits `submit_info` to [`GPUTask::queue`] once this task has finished
+    fn then_submit(self, submit_info: SubmitInfo) -> TaskResult<ChainTask<Self>> {
+        let submit_info = self.completion().iter().fold(submit_info, |info, point| {
+            info.wait(
+                point.handle(),
+                point.value(),
+                vk::PipelineStageFlags::ALL_COMMANDS,
+            )
+        });
+
+        let next = task_from_runner(submit_task::submit(self.queue().clone(), submit_info, None))?;
+
+        Ok(ChainTask::new(self, next))
+    }
+
+    /// Runs the following tasks of the chain on `queue`
+    fn then_execute_on(self, queue: Queue) -> QueueTask<Self> {
+        QueueTask::new(self, queue)
+    }
+
+    /// Task that finishes when both tasks have finished
+    fn join<T: GPUTask>(self, other: T) -> JoinTask<Self, T> {
+        JoinTask::new(self, other)
     }
 }
 
 pub type TaskResult<T> = Result<T, GPUTaskError>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GPUTaskError {
-    SubmitError,
-    PresentError,
-    AcquireError,
-    WaitError,
+    Submit(vk::Result),
+    /// `ERROR_OUT_OF_DATE_KHR` is not a failure, the swapchain has to be rebuilt
+    Present(vk::Result),
+    Acquire(vk::Result),
+    Wait(vk::Result),
+    /// Presentation can only wait on binary semaphores, the task must signal one
+    NoPresentSemaphore,
 }
 
-#[derive(Debug)]
+impl GPUTaskError {
+    pub fn result(&self) -> Option<vk::Result> {
+        match *self {
+            GPUTaskError::Submit(err)
+            | GPUTaskError::Present(err)
+            | GPUTaskError::Acquire(err)
+            | GPUTaskError::Wait(err) => Some(err),
+            GPUTaskError::NoPresentSemaphore => None,
+        }
+    }
+}
+
+impl Display for GPUTaskError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GPUTaskError::Submit(err) => write!(f, "can't submit to queue: {err}"),
+            GPUTaskError::Present(err) => write!(f, "can't present swapchain image: {err}"),
+            GPUTaskError::Acquire(err) => write!(f, "can't acquire swapchain image: {err}"),
+            GPUTaskError::Wait(err) => write!(f, "can't wait for GPU work: {err}"),
+            GPUTaskError::NoPresentSemaphore => {
+                write!(f, "task signals no binary semaphore to present after")
+            }
+        }
+    }
+}
+
+impl Error for GPUTaskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GPUTaskError::Submit(err)
+            | GPUTaskError::Present(err)
+            | GPUTaskError::Acquire(err)
+            | GPUTaskError::Wait(err) => Some(err),
+            GPUTaskError::NoPresentSemaphore => None,
+        }
+    }
+}
+
+/// Command buffers with the semaphores they wait on and signal.
+/// Values are only used by timeline semaphores and ignored for binary ones.
+#[derive(Debug, Clone, Default)]
 pub struct SubmitInfo {
-    pub wait_semaphore: vk::Semaphore,
-    pub signal_semaphore: vk::Semaphore,
+    pub wait_semaphores: Vec<vk::Semaphore>,
+    pub wait_values: Vec<u64>,
+    pub wait_dst_stage_masks: Vec<vk::PipelineStageFlags>,
+    pub signal_semaphores: Vec<vk::Semaphore>,
+    pub signal_values: Vec<u64>,
     pub command_buffers: Vec<vk::CommandBuffer>,
-    pub wait_dst_stage_mask: vk::PipelineStageFlags,
 }
 
 impl SubmitInfo {
-    pub fn to_vk(&self) -> vk::SubmitInfo<'_> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn command_buffers(mut self, command_buffers: Vec<vk::CommandBuffer>) -> Self {
+        self.command_buffers = command_buffers;
+        self
+    }
+
+    /// Waits before `stage` until `semaphore` is signaled, or reaches `value` for timelines
+    pub fn wait(
+        mut self,
+        semaphore: vk::Semaphore,
+        value: u64,
+        stage: vk::PipelineStageFlags,
+    ) -> Self {
+        self.wait_semaphores.push(semaphore);
+        self.wait_values.push(value);
+        self.wait_dst_stage_masks.push(stage);
+        self
+    }
+
+    pub fn signal(mut self, semaphore: vk::Semaphore, value: u64) -> Self {
+        self.signal_semaphores.push(semaphore);
+        self.signal_values.push(value);
+        self
+    }
+
+    /// Signaled binary semaphores, a timeline can't be signaled to 0 so those are the
+    /// ones without a value
+    pub fn binary_signal_semaphores(&self) -> impl Iterator<Item = vk::Semaphore> + '_ {
+        self.signal_semaphores
+            .iter()
+            .zip(&self.signal_values)
+            .filter(|(_, &value)| value == 0)
+            .map(|(&semaphore, _)| semaphore)
+    }
+
+    pub fn timeline_info(&self) -> vk::TimelineSemaphoreSubmitInfo<'_> {
+        vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(&self.wait_values)
+            .signal_semaphore_values(&self.signal_values)
+    }
+
+    pub fn to_vk<'a>(
+        &'a self,
+        timeline_info: &'a mut vk::TimelineSemaphoreSubmitInfo<'a>,
+    ) -> vk::SubmitInfo<'a> {
         vk::SubmitInfo::default()
             .command_buffers(&self.command_buffers)
-            .wait_dst_stage_mask(std::slice::from_ref(&self.wait_dst_stage_mask))
-            .signal_semaphores(std::slice::from_ref(&self.signal_semaphore))
-            .wait_semaphores(std::slice::from_ref(&self.wait_semaphore))
+            .wait_dst_stage_mask(&self.wait_dst_stage_masks)
+            .wait_semaphores(&self.wait_semaphores)
+            .signal_semaphores(&self.signal_semaphores)
+            .push_next(timeline_info)
+    }
+
+    pub fn submit(&self, queue: &QueueGuard<'_>, fence: vk::Fence) -> VkResult<()> {
+        let mut timeline_info = self.timeline_info();
+        let info = self.to_vk(&mut timeline_info);
+
+        queue.submit(std::slice::from_ref(&info), fence)
     }
 }
 
 #[derive(Debug)]
 pub struct PresentInfo {
     pub swapchain: vk::SwapchainKHR,
-    pub wait_semaphore: vk::Semaphore,
+    pub wait_semaphores: Vec<vk::Semaphore>,
     pub image_index: u32,
 }
 
 impl PresentInfo {
     pub fn to_vk(&self) -> vk::PresentInfoKHR<'_> {
         vk::PresentInfoKHR::default()
-            .wait_semaphores(std::slice::from_ref(&self.wait_semaphore))
+            .wait_semaphores(&self.wait_semaphores)
             .swapchains(std::slice::from_ref(&self.swapchain))
             .image_indices(std::slice::from_ref(&self.image_index))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    #[test]
+    fn test_submit_info_semaphores() {
+        let binary = vk::Semaphore::from_raw(1);
+        let timeline = vk::Semaphore::from_raw(2);
+
+        let info = SubmitInfo::new()
+            .wait(binary, 0, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .wait(timeline, 4, vk::PipelineStageFlags::TRANSFER)
+            .signal(timeline, 5)
+            .signal(binary, 0);
+
+        let mut timeline_info = info.timeline_info();
+        let vk_info = info.to_vk(&mut timeline_info);
+
+        assert_eq!(vk_info.wait_semaphore_count, 2);
+        assert_eq!(vk_info.signal_semaphore_count, 2);
+        assert!(!vk_info.p_next.is_null());
+        assert_eq!(info.wait_values, vec![0, 4]);
+        assert_eq!(info.signal_values, vec![5, 0]);
+        assert_eq!(
+            info.binary_signal_semaphores().collect::<Vec<_>>(),
+            vec![binary]
+        );
+    }
+}
@@ -1,26 +1,80 @@
-use crate::gfx_debug_log;
+use crate::graphics::debug_utils::DeviceDebugUtilsFns;
 use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use crate::{gfx_debug_exec, gfx_debug_log};
 use ash::prelude::VkResult;
 use ash::vk;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Semaphore {
     handle: vk::Semaphore,
-    device: Rc<Device>,
+    semaphore_type: vk::SemaphoreType,
+    device: Arc<Device>,
 }
 
 impl Semaphore {
-    pub fn new(device: Rc<Device>) -> VkResult<Self> {
+    pub fn new(device: Arc<Device>) -> VkResult<Self> {
         let create_info = vk::SemaphoreCreateInfo::default();
         let handle = device.create(&create_info)?;
 
-        Ok(Self { handle, device })
+        Ok(Self {
+            handle,
+            semaphore_type: vk::SemaphoreType::BINARY,
+            device,
+        })
+    }
+
+    /// Creates a timeline semaphore, a counter that only increases.
+    /// It can be waited on and signaled from both the host and the GPU.
+    pub fn timeline(device: Arc<Device>, initial_value: u64) -> VkResult<Self> {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+        let handle = device.create(&create_info)?;
+
+        Ok(Self {
+            handle,
+            semaphore_type: vk::SemaphoreType::TIMELINE,
+            device,
+        })
     }
 
     pub fn handle(&self) -> vk::Semaphore {
         self.handle
     }
+
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.handle, name));
+    }
+
+    pub fn is_timeline(&self) -> bool {
+        self.semaphore_type == vk::SemaphoreType::TIMELINE
+    }
+
+    /// Current counter value of a timeline semaphore
+    pub fn value(&self) -> VkResult<u64> {
+        debug_assert!(self.is_timeline());
+        self.device.semaphore_value(self.handle)
+    }
+
+    /// Blocks until the counter of a timeline semaphore reaches `value`
+    pub fn wait(&self, value: u64, timeout: u64) -> VkResult<()> {
+        debug_assert!(self.is_timeline());
+        self.device
+            .wait_semaphores(&[(self.handle, value)], true, timeout)
+    }
+
+    /// Sets the counter of a timeline semaphore from the host
+    pub fn signal(&self, value: u64) -> VkResult<()> {
+        debug_assert!(self.is_timeline());
+        self.device.signal_semaphore(self.handle, value)
+    }
+
+    pub fn as_shared(&self) -> SharedSemaphore {
+        SharedSemaphore::from(self)
+    }
 }
 
 impl Drop for Semaphore {
@@ -30,6 +84,52 @@ impl Drop for Semaphore {
     }
 }
 
+/// Non owning reference to a timeline [`Semaphore`]
+#[derive(Debug, Clone)]
+pub struct SharedSemaphore {
+    handle: vk::Semaphore,
+    device: Arc<Device>,
+}
+
+impl SharedSemaphore {
+    pub fn from(semaphore: &Semaphore) -> Self {
+        Self {
+            handle: semaphore.handle,
+            device: semaphore.device.clone(),
+        }
+    }
+
+    pub fn handle(&self) -> vk::Semaphore {
+        self.handle
+    }
+
+    pub fn value(&self) -> VkResult<u64> {
+        self.device.semaphore_value(self.handle)
+    }
+
+    pub fn wait(&self, value: u64, timeout: u64) -> VkResult<()> {
+        self.device
+            .wait_semaphores(&[(self.handle, value)], true, timeout)
+    }
+
+    pub fn signal(&self, value: u64) -> VkResult<()> {
+        self.device.signal_semaphore(self.handle, value)
+    }
+}
+
+pub trait DeviceSemaphoreFns {
+    fn semaphore_value(&self, semaphore: vk::Semaphore) -> VkResult<u64>;
+    /// Waits for all or, without `wait_all`, any of the timeline semaphores
+    /// to reach their value
+    fn wait_semaphores(
+        &self,
+        semaphores: &[(vk::Semaphore, u64)],
+        wait_all: bool,
+        timeout: u64,
+    ) -> VkResult<()>;
+    fn signal_semaphore(&self, semaphore: vk::Semaphore, value: u64) -> VkResult<()>;
+}
+
 impl DeviceCreateExtend<vk::SemaphoreCreateInfo<'_>, vk::Semaphore> for Device {
     fn create(&self, create_info: &vk::SemaphoreCreateInfo<'_>) -> VkResult<vk::Semaphore> {
         unsafe { self.handle().create_semaphore(create_info, None) }
@@ -38,9 +138,41 @@ impl DeviceCreateExtend<vk::SemaphoreCreateInfo<'_>, vk::Semaphore> for Device {
 
 impl DeviceDestroyExtend<vk::Semaphore> for Device {
     fn destroy(&self, vk_struct: vk::Semaphore) {
-        self.wait_idle().unwrap();
-        unsafe {
-            self.handle().destroy_semaphore(vk_struct, None);
+        self.defer_destroy(move |device| unsafe {
+            device.handle().destroy_semaphore(vk_struct, None);
+        });
+    }
+}
+
+impl DeviceSemaphoreFns for Device {
+    fn semaphore_value(&self, semaphore: vk::Semaphore) -> VkResult<u64> {
+        unsafe { self.handle().get_semaphore_counter_value(semaphore) }
+    }
+
+    fn wait_semaphores(
+        &self,
+        semaphores: &[(vk::Semaphore, u64)],
+        wait_all: bool,
+        timeout: u64,
+    ) -> VkResult<()> {
+        let (handles, values): (Vec<_>, Vec<_>) = semaphores.iter().copied().unzip();
+
+        let mut wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&handles)
+            .values(&values);
+
+        if !wait_all {
+            wait_info = wait_info.flags(vk::SemaphoreWaitFlags::ANY);
         }
+
+        unsafe { self.handle().wait_semaphores(&wait_info, timeout) }
+    }
+
+    fn signal_semaphore(&self, semaphore: vk::Semaphore, value: u64) -> VkResult<()> {
+        let signal_info = vk::SemaphoreSignalInfo::default()
+            .semaphore(semaphore)
+            .value(value);
+
+        unsafe { self.handle().signal_semaphore(&signal_info) }
     }
 }
@@ -1,9 +1,14 @@
 use ash::vk;
 
-use crate::graphics::{device::Queue, sync::GPUTaskError};
+use crate::graphics::{
+    device::{Queue, QueueGuard},
+    sync::GPUTaskError,
+};
 
-use super::{fence::SharedFence, GPUTask, GPUTaskRunner, SubmitInfo};
+use super::{fence::SharedFence, timeline::TimelinePoint, GPUTask, GPUTaskRunner, SubmitInfo};
 
+/// Submits `submit_info` to `queue`. The submission also signals the next value
+/// of the queue timeline, waiting on the task waits for it and for `fence`.
 pub fn submit(
     queue: Queue,
     submit_info: SubmitInfo,
@@ -26,12 +31,17 @@ impl GPUTaskRunner for SubmitTaskRunner {
     type Output = SubmitTask;
 
     fn run_task(self) -> super::TaskResult<Self::Output> {
-        let task = SubmitTask { 
+        let queue = self.queue.lock();
+
+        let task = SubmitTask {
+            point: self.queue.timeline().next_point(),
             info: self.submit_info,
             fence: self.fence,
+            queue: self.queue.clone(),
         };
 
-        task.run(self.queue)?;
+        task.run(&queue)?;
+        drop(queue);
 
         Ok(task)
     }
@@ -40,7 +50,9 @@ impl GPUTaskRunner for SubmitTaskRunner {
 #[derive(Debug)]
 pub struct SubmitTask {
     fence: Option<SharedFence>,
+    point: TimelinePoint,
     info: SubmitInfo,
+    queue: Queue,
 }
 
 impl SubmitTask {
@@ -49,31 +61,44 @@ impl SubmitTask {
             Some(value) => value.handle(),
             None => vk::Fence::null(),
         }
-    } 
-}
-
-impl GPUTask for SubmitTask {
-    type Output = ();
-
-    fn run(&self, queue: Queue) -> super::TaskResult<()> {
-        let info = self.info.to_vk();
+    }
 
+    fn run(&self, queue: &QueueGuard<'_>) -> super::TaskResult<()> {
         let fence = self.get_raw_fence();
 
-        queue.submit(std::slice::from_ref(&info), fence)
-            .map_err(|_| GPUTaskError::SubmitError)
+        self.info
+            .clone()
+            .signal(self.point.handle(), self.point.value())
+            .submit(queue, fence)
+            .map_err(GPUTaskError::Submit)
+    }
+
+    pub fn point(&self) -> &TimelinePoint {
+        &self.point
     }
+}
+
+impl GPUTask for SubmitTask {
+    type Output = ();
 
     fn wait_result(&self) -> super::TaskResult<Self::Output> {
         if let Some(fence) = self.fence.as_ref() {
-            fence.wait(u64::MAX).map_err(|_| GPUTaskError::WaitError)?;
-            fence.reset();
+            fence.wait(u64::MAX).map_err(GPUTaskError::Wait)?;
+            fence.reset().map_err(GPUTaskError::Wait)?;
         }
 
-        Ok(())
+        self.point.wait(u64::MAX).map_err(GPUTaskError::Wait)
+    }
+
+    fn get_signal_semaphores(&self) -> Vec<vk::Semaphore> {
+        self.info.binary_signal_semaphores().collect()
+    }
+
+    fn completion(&self) -> Vec<TimelinePoint> {
+        vec![self.point.clone()]
     }
 
-    fn get_signal_semaphore(&self) -> vk::Semaphore {
-        self.info.signal_semaphore
+    fn queue(&self) -> &Queue {
+        &self.queue
     }
 }
@@ -0,0 +1,82 @@
+use super::semaphore::Semaphore;
+use crate::gfx_debug_exec;
+use crate::graphics::device::Device;
+use ash::prelude::VkResult;
+use ash::vk;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Timeline semaphore that hands out increasing values to submissions.
+/// Every [`Queue`](crate::graphics::device::Queue) owns one, its values are signaled
+/// in submission order, so values are reserved under [`Queue::lock`](crate::graphics::device::Queue::lock).
+#[derive(Debug)]
+pub struct Timeline {
+    semaphore: Semaphore,
+    last_value: AtomicU64,
+}
+
+impl Timeline {
+    pub fn new(device: Arc<Device>) -> VkResult<Self> {
+        Ok(Self {
+            semaphore: Semaphore::timeline(device, 0)?,
+            last_value: AtomicU64::new(0),
+        })
+    }
+
+    pub fn handle(&self) -> vk::Semaphore {
+        self.semaphore.handle()
+    }
+
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.semaphore.set_object_name(name));
+    }
+
+    /// Reserves the next value. The submission it is handed to has to signal it.
+    pub fn next_point(self: &Arc<Self>) -> TimelinePoint {
+        let value = self.last_value.fetch_add(1, Ordering::Relaxed) + 1;
+
+        TimelinePoint {
+            timeline: self.clone(),
+            value,
+        }
+    }
+
+    /// Last value handed out by [`Timeline::next_point`]
+    pub fn last_value(&self) -> u64 {
+        self.last_value.load(Ordering::Relaxed)
+    }
+
+    /// Value the GPU has reached
+    pub fn value(&self) -> VkResult<u64> {
+        self.semaphore.value()
+    }
+
+    pub fn wait(&self, value: u64, timeout: u64) -> VkResult<()> {
+        self.semaphore.wait(value, timeout)
+    }
+}
+
+/// Value of a [`Timeline`] signaled when a submission has finished
+#[derive(Debug, Clone)]
+pub struct TimelinePoint {
+    timeline: Arc<Timeline>,
+    value: u64,
+}
+
+impl TimelinePoint {
+    pub fn handle(&self) -> vk::Semaphore {
+        self.timeline.handle()
+    }
+
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    pub fn is_reached(&self) -> VkResult<bool> {
+        Ok(self.timeline.value()? >= self.value)
+    }
+
+    pub fn wait(&self, timeout: u64) -> VkResult<()> {
+        self.timeline.wait(self.value, timeout)
+    }
+}
@@ -1,21 +1,108 @@
+use super::debug_utils::DeviceDebugUtilsFns;
 use super::device::{Device, DeviceCreateExtend, DeviceDestroyExtend, VulkanDevice};
+use super::memory::{Allocation, AllocationDescription, DeviceMemoryFns};
+use crate::gfx_debug_exec;
 use ash::prelude::VkResult;
 use ash::vk;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub mod swapchain_image;
 
-#[derive(Debug, Clone)]
+/// Image together with its view and optional sampler.
+/// Images created with [`Image::new`] own their memory; all resources are freed on drop.
+#[derive(Debug)]
 pub struct Image {
     extent: vk::Extent3D,
+    format: vk::Format,
     image: vk::Image,
     image_view: vk::ImageView,
     sampler: Option<vk::Sampler>,
+    allocation: Option<Allocation>,
+    level_count: u32,
     layer_count: u32,
-    device: Rc<Device>,
+    device: Arc<Device>,
 }
 
 impl Image {
+    pub fn new(device: Arc<Device>, description: &ImageDescription) -> VkResult<Self> {
+        let (image_type, flags) = match description.view_type {
+            vk::ImageViewType::TYPE_1D | vk::ImageViewType::TYPE_1D_ARRAY => {
+                (vk::ImageType::TYPE_1D, vk::ImageCreateFlags::empty())
+            }
+            vk::ImageViewType::TYPE_3D => (vk::ImageType::TYPE_3D, vk::ImageCreateFlags::empty()),
+            vk::ImageViewType::CUBE | vk::ImageViewType::CUBE_ARRAY => (
+                vk::ImageType::TYPE_2D,
+                vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            ),
+            _ => (vk::ImageType::TYPE_2D, vk::ImageCreateFlags::empty()),
+        };
+
+        let image_info = vk::ImageCreateInfo::default()
+            .flags(flags)
+            .image_type(image_type)
+            .format(description.format)
+            .extent(description.extent)
+            .mip_levels(description.level_count)
+            .array_layers(description.layer_count)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(description.tiling)
+            .usage(description.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = device.create(&image_info)?;
+
+        let requirements = unsafe { device.handle().get_image_memory_requirements(image) };
+        let allocation_description = AllocationDescription::new(requirements)
+            .required_properties(description.properties)
+            .linear(description.tiling == vk::ImageTiling::LINEAR);
+
+        let allocation = match device.allocate_memory(&allocation_description) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy(image);
+                return Err(e);
+            }
+        };
+
+        // From here on `result` owns every handle, so an early return frees them
+        let mut result = Self {
+            extent: description.extent,
+            format: description.format,
+            image,
+            image_view: vk::ImageView::null(),
+            sampler: None,
+            allocation: Some(allocation),
+            level_count: description.level_count,
+            layer_count: description.layer_count,
+            device,
+        };
+
+        let allocation = result.allocation.as_ref().unwrap();
+        unsafe {
+            result.device.handle().bind_image_memory(
+                image,
+                allocation.memory(),
+                allocation.offset(),
+            )?;
+        }
+
+        let image_view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(description.view_type)
+            .format(description.format)
+            .subresource_range(result.subresource_range(description.aspect_flags));
+
+        result.image_view = result.device.create(&image_view_info)?;
+
+        if let Some(sampler) = description.sampler.as_ref() {
+            let sampler_info = sampler.to_vk(description.level_count);
+            result.sampler = Some(result.device.create(&sampler_info)?);
+        }
+
+        Ok(result)
+    }
+
     pub fn image(&self) -> vk::Image {
         self.image
     }
@@ -31,6 +118,122 @@ impl Image {
     pub fn extent(&self) -> vk::Extent3D {
         self.extent
     }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.level_count
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.layer_count
+    }
+
+    pub fn subresource_range(
+        &self,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: self.level_count,
+            base_array_layer: 0,
+            layer_count: self.layer_count,
+        }
+    }
+
+    /// Names the image together with its view and sampler
+    pub fn set_object_name(&self, name: &str) {
+        gfx_debug_exec!(self.device.set_object_name(self.image, name));
+
+        if self.image_view != vk::ImageView::null() {
+            gfx_debug_exec!(self
+                .device
+                .set_object_name(self.image_view, &format!("{name} view")));
+        }
+        if let Some(sampler) = self.sampler {
+            gfx_debug_exec!(self
+                .device
+                .set_object_name(sampler, &format!("{name} sampler")));
+        }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if let Some(sampler) = self.sampler {
+            self.device.destroy(sampler);
+        }
+        if self.image_view != vk::ImageView::null() {
+            self.device.destroy(self.image_view);
+        }
+        // Images without an allocation belong to someone else, e.g. a swapchain
+        if self.allocation.is_some() {
+            self.device.destroy(self.image);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerDescription {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode: vk::SamplerAddressMode,
+    pub max_anisotropy: Option<f32>,
+}
+
+impl SamplerDescription {
+    pub fn nearest() -> Self {
+        Self::default()
+    }
+
+    pub fn linear() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            ..Self::default()
+        }
+    }
+
+    pub fn address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    pub fn max_anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = Some(max_anisotropy);
+        self
+    }
+
+    fn to_vk(self, level_count: u32) -> vk::SamplerCreateInfo<'static> {
+        vk::SamplerCreateInfo::default()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .anisotropy_enable(self.max_anisotropy.is_some())
+            .max_anisotropy(self.max_anisotropy.unwrap_or(1.0))
+            .min_lod(0.0)
+            .max_lod(level_count as f32)
+    }
+}
+
+impl Default for SamplerDescription {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -44,6 +247,7 @@ pub struct ImageDescription {
     pub properties: vk::MemoryPropertyFlags,
     pub level_count: u32,
     pub layer_count: u32,
+    pub sampler: Option<SamplerDescription>,
 }
 
 impl ImageDescription {
@@ -75,6 +279,46 @@ impl ImageDescription {
         self.format = format;
         self
     }
+
+    pub fn usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn view_type(mut self, view_type: vk::ImageViewType) -> Self {
+        self.view_type = view_type;
+        self
+    }
+
+    pub fn aspect_flags(mut self, aspect_flags: vk::ImageAspectFlags) -> Self {
+        self.aspect_flags = aspect_flags;
+        self
+    }
+
+    pub fn tiling(mut self, tiling: vk::ImageTiling) -> Self {
+        self.tiling = tiling;
+        self
+    }
+
+    pub fn properties(mut self, properties: vk::MemoryPropertyFlags) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    pub fn level_count(mut self, level_count: u32) -> Self {
+        self.level_count = level_count;
+        self
+    }
+
+    pub fn layer_count(mut self, layer_count: u32) -> Self {
+        self.layer_count = layer_count;
+        self
+    }
+
+    pub fn sampler(mut self, sampler: SamplerDescription) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
 }
 
 impl Default for ImageDescription {
@@ -89,6 +333,7 @@ impl Default for ImageDescription {
             properties: vk::MemoryPropertyFlags::default(),
             level_count: 1,
             layer_count: 1,
+            sampler: None,
         }
     }
 }
@@ -101,9 +346,9 @@ impl DeviceCreateExtend<vk::ImageCreateInfo<'_>, vk::Image> for Device {
 
 impl DeviceDestroyExtend<vk::Image> for Device {
     fn destroy(&self, vk_struct: vk::Image) {
-        unsafe {
-            self.handle().destroy_image(vk_struct, None);
-        }
+        self.defer_destroy(move |device| unsafe {
+            device.handle().destroy_image(vk_struct, None);
+        });
     }
 }
 
@@ -115,9 +360,9 @@ impl DeviceCreateExtend<vk::ImageViewCreateInfo<'_>, vk::ImageView> for Device {
 
 impl DeviceDestroyExtend<vk::ImageView> for Device {
     fn destroy(&self, vk_struct: vk::ImageView) {
-        unsafe {
-            self.handle().destroy_image_view(vk_struct, None);
-        }
+        self.defer_destroy(move |device| unsafe {
+            device.handle().destroy_image_view(vk_struct, None);
+        });
     }
 }
 
@@ -129,8 +374,8 @@ impl DeviceCreateExtend<vk::SamplerCreateInfo<'_>, vk::Sampler> for Device {
 
 impl DeviceDestroyExtend<vk::Sampler> for Device {
     fn destroy(&self, vk_struct: vk::Sampler) {
-        unsafe {
-            self.handle().destroy_sampler(vk_struct, None);
-        }
+        self.defer_destroy(move |device| unsafe {
+            device.handle().destroy_sampler(vk_struct, None);
+        });
     }
 }
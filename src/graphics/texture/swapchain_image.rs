@@ -1,19 +1,20 @@
 use super::Image;
-use crate::graphics::device::{Device, DeviceCreateExtend, DeviceDestroyExtend};
+use crate::graphics::device::{Device, DeviceCreateExtend};
+use ash::prelude::VkResult;
 use ash::vk;
 use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct SwapchainImage(Image);
 
 impl SwapchainImage {
     pub fn new(
-        device: Rc<Device>,
+        device: Arc<Device>,
         image: vk::Image,
         format: vk::Format,
         extent: vk::Extent3D,
-    ) -> Self {
+    ) -> VkResult<Self> {
         let image_view_info = vk::ImageViewCreateInfo::default()
             .image(image)
             .view_type(vk::ImageViewType::TYPE_2D)
@@ -32,27 +33,21 @@ impl SwapchainImage {
                 layer_count: 1,
             });
 
-        let image_view = device
-            .create(&image_view_info)
-            .expect("Error while create image view for swapchain image");
+        let image_view = device.create(&image_view_info)?;
 
         let image_internal = Image {
             extent,
+            format,
             image,
             image_view,
             sampler: None,
+            allocation: None,
+            level_count: 1,
             layer_count: 1,
             device,
         };
 
-        Self(image_internal)
-    }
-
-    pub fn destroy(&self) {
-        if let Some(sampler) = self.sampler {
-            self.device.destroy(sampler);
-        }
-        self.device.destroy(self.image_view);
+        Ok(Self(image_internal))
     }
 }
 
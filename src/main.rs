@@ -15,17 +15,43 @@ const APP_MINOR_VERSION: &str = env!("CARGO_PKG_VERSION_MINOR");
 const APP_PATCH_VERSION: &str = env!("CARGO_PKG_VERSION_PATCH");
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 
+const HEADLESS_FRAME_COUNT: u32 = 60;
+
 fn main() {
     init_logger();
 
-    let event_loop = create_event_loop();
     let mut app = App::new();
 
+    if let Some(frame_count) = headless_frame_count() {
+        log::info!("Begin headless launch");
+        if let Err(e) = app.run_headless(frame_count) {
+            log::error!("Headless launch failed: {e}");
+            std::process::exit(1);
+        }
+        log::info!("end headless launch");
+        return;
+    }
+
+    let event_loop = create_event_loop();
+
     log::info!("Begin launch");
     event_loop.run_app(&mut app).unwrap();
     log::info!("end launch");
 }
 
+/// Returns the number of frames to render when launched with `--headless [frames]`
+fn headless_frame_count() -> Option<u32> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--headless");
+
+    args.next()?;
+
+    Some(
+        args.next()
+            .and_then(|frames| frames.parse().ok())
+            .unwrap_or(HEADLESS_FRAME_COUNT),
+    )
+}
+
 #[inline]
 fn init_logger() {
     let env_log = env_logger::Env::new().filter_or("LPPS_LOG", "DEBUG");
@@ -1,8 +1,9 @@
+use ash::ext::headless_surface;
 use ash::khr::{wayland_surface, win32_surface, xcb_surface, xlib_surface};
 use ash::prelude::VkResult;
 use ash::vk::{
-    self, SurfaceKHR, WaylandSurfaceCreateInfoKHR, Win32SurfaceCreateInfoKHR,
-    XcbSurfaceCreateInfoKHR, XlibSurfaceCreateInfoKHR,
+    self, HeadlessSurfaceCreateInfoEXT, SurfaceKHR, WaylandSurfaceCreateInfoKHR,
+    Win32SurfaceCreateInfoKHR, XcbSurfaceCreateInfoKHR, XlibSurfaceCreateInfoKHR,
 };
 use ash::{Entry, Instance};
 
@@ -18,8 +19,14 @@ pub unsafe fn create_surface<T>(
 where
     T: HasDisplayHandle + HasWindowHandle,
 {
-    let raw_display_handle = handle.display_handle().unwrap().as_raw();
-    let raw_window_handle = handle.window_handle().unwrap().as_raw();
+    let raw_display_handle = handle
+        .display_handle()
+        .map_err(|_| vk::Result::ERROR_INITIALIZATION_FAILED)?
+        .as_raw();
+    let raw_window_handle = handle
+        .window_handle()
+        .map_err(|_| vk::Result::ERROR_INITIALIZATION_FAILED)?
+        .as_raw();
 
     match (raw_display_handle, raw_window_handle) {
         (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
@@ -77,11 +84,22 @@ where
     }
 }
 
+pub unsafe fn create_headless_surface(entry: &Entry, instance: &Instance) -> VkResult<SurfaceKHR> {
+    let surface_info = HeadlessSurfaceCreateInfoEXT::default();
+
+    let surface_fns = headless_surface::Instance::new(entry, instance);
+
+    surface_fns.create_headless_surface(&surface_info, None)
+}
+
 pub fn enumerate_required_extensions<T>(handle: &T) -> VkResult<Vec<String>>
 where
     T: HasDisplayHandle,
 {
-    let raw_display_handle = handle.display_handle().unwrap().as_raw();
+    let raw_display_handle = handle
+        .display_handle()
+        .map_err(|_| vk::Result::ERROR_INITIALIZATION_FAILED)?
+        .as_raw();
 
     let surface_extension = ash::khr::surface::NAME.to_str().unwrap().to_owned();
     match raw_display_handle {
@@ -104,3 +122,10 @@ where
         _ => Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT),
     }
 }
+
+pub fn enumerate_headless_extensions() -> Vec<String> {
+    vec![
+        ash::khr::surface::NAME.to_str().unwrap().to_owned(),
+        headless_surface::NAME.to_str().unwrap().to_owned(),
+    ]
+}
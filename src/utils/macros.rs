@@ -10,6 +10,6 @@ macro_rules! gfx_debug_exec {
 #[macro_export]
 macro_rules! gfx_debug_log {
     ($($arg:tt)+) => {
-        crate::gfx_debug_exec!(log::debug!(target: "rust_engine::graphics", $($arg)+))
+        $crate::gfx_debug_exec!(log::debug!(target: "rust_engine::graphics", $($arg)+))
     };
 }
@@ -27,7 +27,7 @@ impl IntoExtent3D for ash::vk::Extent2D {
         ash::vk::Extent3D {
             width,
             height,
-            depth: 0,
+            depth: 1,
         }
     }
 }